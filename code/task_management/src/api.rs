@@ -1,15 +1,36 @@
-use core::{future::{poll_fn, Future}, ops::DerefMut, task::Poll};
-use alloc::sync::Arc;
+use core::{any::Any, future::{poll_fn, Future}, ops::DerefMut, task::Poll, time::Duration};
+use alloc::{boxed::Box, string::String, sync::Arc};
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+use alloc::collections::BTreeMap;
 #[cfg(feature = "preempt")]
 use kernel_guard::KernelGuardIf;
 use riscv::register::sstatus;
 
+#[cfg(feature = "trace")]
+use crate::trace::{self, TraceEvent};
 pub use crate::task::TaskContext;
+pub use crate::timer::{Sleep, DEFAULT_TIMEBASE_FREQUENCY};
+pub use crate::task_local::LocalKey;
+#[cfg(feature = "stack_guard_page")]
+pub use crate::stack::StackGuardPage;
+#[cfg(feature = "stack_stats")]
+pub use crate::stack::{CpuStackPoolStats, StackPoolStats};
+#[cfg(feature = "switch_hook")]
+pub use crate::task::TaskSwitchIf;
+pub use crate::task::TaskStats;
+pub use crate::processor::CpuStats;
+#[cfg(feature = "ipi")]
+pub use crate::processor::IpiIf;
+#[cfg(feature = "hpm")]
+pub use crate::hpm::HpmStats;
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub use task_queues::moic::{MoicContext, TaskId as MoicTaskId};
 
 // ------处理器初始化------
 
 /// 需要在主处理器上调用，且仅调用一次。
 /// 初始化函数运行的处理器。
+/// 任务的CPU亲和性以u64位图表示，因此`cpu_num`不能超过64。
 #[no_mangle]
 pub fn init_main_processor(cpu_id: usize, cpu_num: usize) {
     Processor::init_main_processor(cpu_id, cpu_num);
@@ -81,6 +102,12 @@ pub fn start_secondary_processor() -> ! {
     loop { }
 }
 
+/// 设置time寄存器递增的频率（Hz），睡眠等与时间相关的接口依赖该频率，未设置时使用`DEFAULT_TIMEBASE_FREQUENCY`。
+/// 启用了trap_handler模块的timer feature时，会在其初始化过程中自动设置。
+pub fn init_timebase_frequency(frequency: usize) {
+    timer::set_timebase_frequency(frequency);
+}
+
 pub fn current_processor_id() -> usize {
     Processor::with_current(|processor| {
         processor.id()
@@ -90,43 +117,181 @@ pub fn current_processor_id() -> usize {
 // ------任务创建------
 
 use spinlock::SpinNoIrq;
-use crate::{processor::{self, Processor}, task::{preempt_switch_entry, switch_entry, TaskInner, TaskState}};
-pub use crate::task::Task;
+use crate::{processor::{self, Processor}, stack::{MIN_TASK_STACK_SIZE, TASK_STACK_SIZE, TASK_STACK_SIZE_ALIGN}, task::{preempt_switch_entry, switch_entry, KillResult, TaskInner, TaskOptions, WaitOwner}, registry, timer};
+pub use crate::task::{Task, TaskKind, TaskState};
+
+/// 创建任务时加入的调度器
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SpawnTarget {
+    /// 当前CPU的调度器
+    Local,
+    /// 全局调度器
+    Global,
+    /// 指定id的CPU的调度器
+    Cpu(usize),
+}
+
+/// 创建任务失败的原因
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SpawnError {
+    /// 优先级无效
+    InvalidPriority,
+    /// CPU亲和性无效，或不允许任务在指定的CPU上运行
+    InvalidAffinity,
+    /// 栈大小小于下限
+    InvalidStackSize,
+    /// 指定的CPU不存在
+    InvalidCpu,
+}
+
+/// 用于配置并创建任务
+/// 通过`spawn`创建线程，通过`spawn_async`创建协程。
+pub struct TaskBuilder {
+    name: Option<String>,
+    priority: Option<isize>,
+    stack_size: usize,
+    cpu_set: u64,
+    target: SpawnTarget,
+    user_data: usize,
+    ext: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl TaskBuilder {
+    /// 默认配置：无任务名、使用调度器的默认优先级、默认栈大小、可在所有CPU上运行、加入当前CPU的调度器
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            priority: None,
+            stack_size: TASK_STACK_SIZE,
+            cpu_set: u64::MAX,
+            target: SpawnTarget::Local,
+            user_data: 0,
+            ext: None,
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    pub fn priority(mut self, priority: isize) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// 栈大小会向上取整到4KiB的倍数
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// `cpu_set`为位图，第i位为1代表任务可以在id为i的CPU上运行。
+    pub fn affinity(mut self, cpu_set: u64) -> Self {
+        self.cpu_set = cpu_set;
+        self
+    }
+
+    pub fn target(mut self, target: SpawnTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// 由使用该模块的系统解释的数据，见`TaskInner::user_data`
+    pub fn user_data(mut self, user_data: usize) -> Self {
+        self.user_data = user_data;
+        self
+    }
+
+    /// 附加在任务上的数据，可通过`TaskInner::ext`或`current_ext`读取
+    pub fn ext<T: Any + Send + Sync>(mut self, ext: T) -> Self {
+        self.ext = Some(Box::new(ext));
+        self
+    }
+
+    /// 创建线程
+    pub fn spawn<F>(mut self, f: F) -> Result<Arc<Task>, SpawnError>
+    where F: (FnOnce() -> i32) + Send + 'static {
+        let options = self.options()?;
+        self.submit(TaskInner::new_with_options(f, options))
+    }
+
+    /// 创建协程
+    pub fn spawn_async<F>(mut self, f: F) -> Result<Arc<Task>, SpawnError>
+    where F: Future<Output = i32> + Send + 'static {
+        let options = self.options()?;
+        self.submit(TaskInner::new_async_with_options(f, options))
+    }
+
+    /// 检查配置，并生成创建任务所需的属性
+    fn options(&mut self) -> Result<TaskOptions, SpawnError> {
+        check_affinity(self.cpu_set).map_err(|_| SpawnError::InvalidAffinity)?;
+        if let SpawnTarget::Cpu(cpu_id) = self.target {
+            if cpu_id >= Processor::cpu_num() {
+                return Err(SpawnError::InvalidCpu);
+            }
+            if self.cpu_set & (1 << cpu_id) == 0 {
+                return Err(SpawnError::InvalidAffinity);
+            }
+        }
+        if self.stack_size < MIN_TASK_STACK_SIZE {
+            return Err(SpawnError::InvalidStackSize);
+        }
+        Ok(TaskOptions {
+            name: self.name.take(),
+            stack_size: (self.stack_size + TASK_STACK_SIZE_ALIGN - 1) & !(TASK_STACK_SIZE_ALIGN - 1),
+            ext: self.ext.take(),
+        })
+    }
+
+    /// 设置优先级和亲和性，并将任务加入调度器
+    fn submit(self, task: Arc<Task>) -> Result<Arc<Task>, SpawnError> {
+        task.set_cpu_set(self.cpu_set);
+        task.set_user_data(self.user_data);
+        Processor::with_current(|processor| {
+            if let Some(priority) = self.priority {
+                if !processor.with_local_scheduler(|scheduler| scheduler.set_priority(&task, priority)) {
+                    return Err(SpawnError::InvalidPriority);
+                }
+                task.set_priority_mirror(priority);
+            }
+            #[cfg(feature = "trace")]
+            trace::record(processor.id(), TraceEvent::Spawn, task.id(), 0);
+            match self.target {
+                SpawnTarget::Local => processor.add_task_to_local(task.clone()),
+                SpawnTarget::Global => processor.add_task_to_global(task.clone()),
+                SpawnTarget::Cpu(cpu_id) => processor.add_task_to_remote(cpu_id, task.clone()),
+            }
+            Ok(())
+        })?;
+        Ok(task)
+    }
+}
+
+impl Default for TaskBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// 创建任务并加入全局的调度器
 pub fn spawn_to_global<F>(f: F) -> Arc<Task>
 where F: (FnOnce() -> i32) + Send + 'static {
-    let task = TaskInner::new(f);
-    Processor::with_current(|processor| {
-        processor.add_task_to_global(task.clone());
-    });
-    task
+    TaskBuilder::new().target(SpawnTarget::Global).spawn(f).unwrap()
 }
 pub fn spawn_to_global_async<F>(f: F) -> Arc<Task>
 where F: Future<Output = i32> + Send + 'static {
-    let task = TaskInner::new_async(f);
-    Processor::with_current(|processor| {
-        processor.add_task_to_global(task.clone());
-    });
-    task
+    TaskBuilder::new().target(SpawnTarget::Global).spawn_async(f).unwrap()
 }
 
 /// 创建任务并加入当前CPU的调度器
 pub fn spawn_to_local<F>(f: F) -> Arc<Task>
 where F: (FnOnce() -> i32) + Send + 'static {
-    let task = TaskInner::new(f);
-    Processor::with_current(|processor| {
-        processor.add_task_to_local(task.clone());
-    });
-    task
+    TaskBuilder::new().spawn(f).unwrap()
 }
 pub fn spawn_to_local_async<F>(f: F) -> Arc<Task>
 where F: Future<Output = i32> + Send + 'static {
-    let task = TaskInner::new_async(f);
-    Processor::with_current(|processor| {
-        processor.add_task_to_local(task.clone());
-    });
-    task
+    TaskBuilder::new().spawn_async(f).unwrap()
 }
 
 /// 代表设置的优先级无效的错误
@@ -135,80 +300,172 @@ pub struct InvalidPriorityError;
 /// 在创建时设置了优先级的版本，如果设置的优先级无效则不会创建，并返回Err。
 pub fn spawn_to_global_with_priority<F>(f: F, priority: isize) -> Result<Arc<Task>, InvalidPriorityError>
 where F: (FnOnce() -> i32) + Send + 'static {
-    let task = TaskInner::new(f);
-    let success = Processor::with_current(|processor| {
-        let success = processor.with_local_scheduler(|scheduler| scheduler.set_priority(&task, priority));
-        if success {
-            processor.add_task_to_global(task.clone());
-        }
-        success
-    });
-    if success {
-        Ok(task)
-    }
-    else {
-        Err(InvalidPriorityError)
-    }
+    TaskBuilder::new().target(SpawnTarget::Global).priority(priority).spawn(f).map_err(|_| InvalidPriorityError)
 }
 pub fn spawn_to_global_async_with_priority<F>(f: F, priority: isize) -> Result<Arc<Task>, InvalidPriorityError>
 where F: Future<Output = i32> + Send + 'static {
-    let task = TaskInner::new_async(f);
-    let success = Processor::with_current(|processor| {
-        let success = processor.with_local_scheduler(|scheduler| scheduler.set_priority(&task, priority));
-        if success {
-            processor.add_task_to_global(task.clone());
-        }
-        success
-    });
-    if success {
-        Ok(task)
-    }
-    else {
-        Err(InvalidPriorityError)
-    }
+    TaskBuilder::new().target(SpawnTarget::Global).priority(priority).spawn_async(f).map_err(|_| InvalidPriorityError)
 }
 
 /// 创建任务并加入当前CPU的调度器
 pub fn spawn_to_local_with_priority<F>(f: F, priority: isize) -> Result<Arc<Task>, InvalidPriorityError>
 where F: (FnOnce() -> i32) + Send + 'static {
-    let task = TaskInner::new(f);
-    let success = Processor::with_current(|processor| {
-        let success = processor.with_local_scheduler(|scheduler| scheduler.set_priority(&task, priority));
-        if success {
-            processor.add_task_to_local(task.clone());
-        }
-        success
-    });
-    if success {
-        Ok(task)
-    }
-    else {
-        Err(InvalidPriorityError)
-    }
+    TaskBuilder::new().priority(priority).spawn(f).map_err(|_| InvalidPriorityError)
 }
 pub fn spawn_to_local_async_with_priority<F>(f: F, priority: isize) -> Result<Arc<Task>, InvalidPriorityError>
 where F: Future<Output = i32> + Send + 'static {
-    let task = TaskInner::new_async(f);
-    let success = Processor::with_current(|processor| {
-        let success = processor.with_local_scheduler(|scheduler| scheduler.set_priority(&task, priority));
-        if success {
-            processor.add_task_to_local(task.clone());
-        }
-        success
-    });
-    if success {
-        Ok(task)
+    TaskBuilder::new().priority(priority).spawn_async(f).map_err(|_| InvalidPriorityError)
+}
+
+/// 代表设置的CPU亲和性无效（不包含任何存在的CPU）的错误
+pub struct InvalidAffinityError;
+
+/// 检查CPU亲和性位图是否至少包含一个存在的CPU
+fn check_affinity(cpu_set: u64) -> Result<(), InvalidAffinityError> {
+    let cpu_num = processor::Processor::cpu_num();
+    let valid_cpus = if cpu_num == u64::BITS as usize { u64::MAX } else { (1 << cpu_num) - 1 };
+    if cpu_set & valid_cpus != 0 {
+        Ok(())
     }
     else {
-        Err(InvalidPriorityError)
+        Err(InvalidAffinityError)
     }
 }
 
+/// 在创建时设置了CPU亲和性的版本，如果设置的亲和性无效则不会创建，并返回Err。
+/// `cpu_set`为位图，第i位为1代表任务可以在id为i的CPU上运行。
+pub fn spawn_to_global_with_affinity<F>(f: F, cpu_set: u64) -> Result<Arc<Task>, InvalidAffinityError>
+where F: (FnOnce() -> i32) + Send + 'static {
+    TaskBuilder::new().target(SpawnTarget::Global).affinity(cpu_set).spawn(f).map_err(|_| InvalidAffinityError)
+}
+pub fn spawn_to_global_async_with_affinity<F>(f: F, cpu_set: u64) -> Result<Arc<Task>, InvalidAffinityError>
+where F: Future<Output = i32> + Send + 'static {
+    TaskBuilder::new().target(SpawnTarget::Global).affinity(cpu_set).spawn_async(f).map_err(|_| InvalidAffinityError)
+}
+
+/// 创建任务并加入当前CPU的调度器
+/// 若亲和性不允许任务在当前CPU上运行，则加入全局调度器
+pub fn spawn_to_local_with_affinity<F>(f: F, cpu_set: u64) -> Result<Arc<Task>, InvalidAffinityError>
+where F: (FnOnce() -> i32) + Send + 'static {
+    TaskBuilder::new().affinity(cpu_set).spawn(f).map_err(|_| InvalidAffinityError)
+}
+pub fn spawn_to_local_async_with_affinity<F>(f: F, cpu_set: u64) -> Result<Arc<Task>, InvalidAffinityError>
+where F: Future<Output = i32> + Send + 'static {
+    TaskBuilder::new().affinity(cpu_set).spawn_async(f).map_err(|_| InvalidAffinityError)
+}
+
+/// 修改任务的CPU亲和性
+/// 已经位于调度器中的任务会在被选取时检查亲和性；正在其它CPU上运行的任务会在下一次切换时迁移。
+pub fn set_affinity(task: &Arc<Task>, cpu_set: u64) -> Result<(), InvalidAffinityError> {
+    check_affinity(cpu_set)?;
+    task.set_cpu_set(cpu_set);
+    Ok(())
+}
+
+/// 获取任务的CPU亲和性
+pub fn affinity(task: &Arc<Task>) -> u64 {
+    task.cpu_set()
+}
+
+// ------任务查询------
+
+/// 按id顺序对每个存在的任务执行`f`
+pub fn for_each_task<F>(f: F)
+where F: FnMut(&Arc<Task>) {
+    registry::for_each_task(f)
+}
+
+/// 根据id查找任务，任务已经不存在时返回None
+pub fn lookup(id: u64) -> Option<Arc<Task>> {
+    registry::lookup(id)
+}
+
+/// 输出所有任务的快照，每行一个JSON对象，包含任务的id、名称、类型、状态、最近运行的CPU、优先级、返回值、运行时间和等待的阻塞队列
+/// 可在调试按键或panic处理函数中调用：只尝试获取锁，被占用的锁对应的内容以`"<locked>"`代替
+pub fn dump_tasks<W: core::fmt::Write>(w: &mut W) -> core::fmt::Result {
+    registry::dump_tasks(w)
+}
+
+// ------运行时间统计------
+
+/// 任务的累计运行时间，以及主动让出、被抢占、被唤醒的次数
+pub fn task_stats(task: &Arc<Task>) -> TaskStats {
+    task.stats()
+}
+
+/// 当前任务的运行时间与切换次数统计
+pub fn current_task_stats() -> TaskStats {
+    current_ptr().stats()
+}
+
+/// id为`cpu_id`的CPU的空闲与忙碌时间，`cpu_id`无效时返回None
+/// 执行idle任务的时间计为空闲时间
+pub fn cpu_stats(cpu_id: usize) -> Option<CpuStats> {
+    Processor::cpu_stats(cpu_id)
+}
+
+/// 任务运行期间经过的时钟周期数与退休的指令数
+#[cfg(feature = "hpm")]
+pub fn task_hpm_stats(task: &Arc<Task>) -> HpmStats {
+    task.hpm().stats()
+}
+
+/// 当前任务的硬件性能计数器统计
+#[cfg(feature = "hpm")]
+pub fn current_task_hpm_stats() -> HpmStats {
+    current_ptr().hpm().stats()
+}
+
+// ------调度事件跟踪------
+
+/// 将各CPU记录的调度事件（切换、阻塞、唤醒、创建、退出、抢占、迁移）导出为Chrome trace event格式的JSON
+/// 输出可通过串口保存后，用Perfetto（ui.perfetto.dev）或chrome://tracing查看
+#[cfg(feature = "trace")]
+pub fn export_trace<W: core::fmt::Write>(w: &mut W) -> core::fmt::Result {
+    trace::export(w)
+}
+
+/// 丢弃所有已记录的调度事件
+#[cfg(feature = "trace")]
+pub fn clear_trace() {
+    trace::clear()
+}
+
+// ------栈使用量统计------
+
+/// 任务的栈的最大使用量（字节）
+/// 挂起的线程会在调用时测量；协程运行在CPU的共享栈上，其使用量计入CPU的栈（见`current_stack_peak`）。
+#[cfg(feature = "stack_stats")]
+pub fn task_stack_peak(task: &Arc<Task>) -> usize {
+    task.measure_stack_peak()
+}
+
+/// 当前CPU正在使用的栈的最大使用量（字节）
+/// 在线程中调用时为该线程的栈，在协程中调用时为CPU上协程共享的栈。
+#[cfg(feature = "stack_stats")]
+pub fn current_stack_peak() -> usize {
+    Processor::with_current(|processor| {
+        processor.get_stack_pool_mut().curr_stack_peak_usage().unwrap_or(0)
+    })
+}
+
+/// 所有CPU的栈池的统计信息
+#[cfg(feature = "stack_stats")]
+pub fn stack_pool_stats() -> StackPoolStats {
+    StackPoolStats::global()
+}
+
+/// id为`cpu_id`的CPU的栈池的统计信息，`cpu_id`无效时返回None
+#[cfg(feature = "stack_stats")]
+pub fn cpu_stack_pool_stats(cpu_id: usize) -> Option<CpuStackPoolStats> {
+    CpuStackPoolStats::of(cpu_id)
+}
+
 // ------当前任务管理------
 
 /// 获取当前任务的Arc实例
 /// 向外部暴露的`Task`对象，功能尽可能少，从而保证大部分任务管理功能可以仅使用“当前任务管理”的接口完成。
-/// 目前，获得的`Task`对象只有用于join这一个用途。
+/// 获得的`Task`对象可用于join（见`join`和`join_async`）、kill，以及查询任务的id、名称、状态等信息。
 pub fn current_ptr() -> Arc<Task> {
     Processor::with_current(|processor| {
         processor.current_task().get_current_ptr()
@@ -222,6 +479,14 @@ pub fn current_id() -> u64 {
     })
 }
 
+/// 获取当前任务创建时附加的数据的副本，未附加数据或类型不是`T`时返回None
+/// 较大的数据可以用`Arc`包装后附加，从而使复制的开销较小
+pub fn current_ext<T: Any + Clone>() -> Option<T> {
+    Processor::with_current(|processor| {
+        processor.current_task().get_current_ptr().ext::<T>().cloned()
+    })
+}
+
 /// 改变当前任务的优先级
 /// 返回值代表传入的优先级是否合法、修改是否成功
 pub fn change_current_priority(new_priority: isize) -> Result<(), InvalidPriorityError> {
@@ -231,6 +496,7 @@ pub fn change_current_priority(new_priority: isize) -> Result<(), InvalidPriorit
             scheduler.set_priority(&current, new_priority)
         });
         if success {
+            current.set_priority_mirror(new_priority);
             Ok(())
         }
         else {
@@ -243,32 +509,40 @@ pub fn change_current_priority(new_priority: isize) -> Result<(), InvalidPriorit
 pub fn yield_current_to_local() {
     Processor::with_current(|processor| {
         let current = processor.current_task().get_current_ptr();
-        assert!(current.is_runable());
+        assert!(current.is_running());
     });
     switch_entry(true);
 }
 pub async fn yield_current_to_local_async() {
     Processor::with_current(|processor| {
         let current = processor.current_task().get_current_ptr();
-        assert!(current.is_runable());
+        assert!(current.is_running());
     });
     yield_helper().await;
 }
 
-// 暂时不考虑，因为可能出现同步问题：放入调度器后，还未保存上下文，就被其它CPU核心取出执行。
-// 原因：目前运行和就绪状态都用TaskState::Runable表示。
-// 且即使将运行和就绪状态区分开，调度器也没有“仅选取就绪态任务”的接口。
-// /// 让权，且将任务放回全局调度器（可能被其它CPU核心执行）
-// pub fn yield_current_to_global() {
-//     unimplemented!()
-// }
-// pub async fn yield_current_to_global_async() {
-//     unimplemented!()
-// }
+/// 主动让权一次，且将任务放回全局调度器（可能被其它CPU核心执行）
+/// 任务在切换过程中、上下文保存完成后才会被放入全局调度器，因此不会在保存完成前被其它CPU核心取出执行。
+pub fn yield_current_to_global() {
+    Processor::with_current(|processor| {
+        let current = processor.current_task().get_current_ptr();
+        assert!(current.is_running());
+        current.set_requeue_to_global();
+    });
+    switch_entry(true);
+}
+pub async fn yield_current_to_global_async() {
+    Processor::with_current(|processor| {
+        let current = processor.current_task().get_current_ptr();
+        assert!(current.is_running());
+        current.set_requeue_to_global();
+    });
+    yield_helper().await;
+}
 
 /// 用于使协程让出一次，切换到其它任务、
 /// 功能相当于线程的switch_entry()
-async fn yield_helper() {
+pub(crate) async fn yield_helper() {
     let mut flag = false;
     poll_fn(|_cx| {
         flag = !flag;
@@ -288,10 +562,187 @@ pub async fn block_current_async(block_queue: &mut BlockQueue) {
     block_queue.block_current_async().await
 }
 
-// 目前先不考虑该接口，因为其涉及到时间与中断
-// /// 睡眠
-// pub fn sleep_current(duration: Duration)
-// pub async fn sleep_current(duration: Duration)
+/// 睡眠（线程版本）
+/// 任务在当前CPU的定时器列表中等待，由时钟中断处理程序（见`check_timers_current`）唤醒。
+/// 以带超时的阻塞实现：超时事件带有序号，已经结束的睡眠的事件不会唤醒任务。
+pub fn sleep_current(duration: Duration) {
+    // 队列只属于当前任务，不会被唤醒操作唤醒，因此阻塞只会因超时而结束
+    let queue = BlockQueue::new();
+    queue.block_current_timeout(duration);
+}
+/// 睡眠（协程版本）
+pub async fn sleep_current_async(duration: Duration) {
+    sleep(duration).await
+}
+
+/// 创建一个在`duration`之后完成的Future，可以在任意协程中await。
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::new(duration)
+}
+
+/// 唤醒当前CPU上所有已到期的睡眠任务，需要在时钟中断处理程序中调用。
+pub fn check_timers_current() {
+    timer::check_timers_current()
+}
+
+// ------由MOIC唤醒任务------
+// 每个事件（外部中断，或来自某一上下文的IPC）对应一个阻塞队列，等待事件的任务阻塞在其中，因此同一事件可以有多个等待的任务。
+// 事件的队列中有任务时，一个分发协程被注册到全局调度器的MOIC中：事件到来时由MOIC将其加入就绪队列，其运行时撤销注册并唤醒队列中的所有任务。
+// 队列中的最后一个任务被终止时，分发协程也随之被终止并撤销注册，因此MOIC中不会残留已经没有等待者的注册。
+
+/// 由MOIC产生、可以被等待的事件
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+enum MoicEvent {
+    /// 外部中断
+    ExtIntr(usize),
+    /// 来自某一上下文的IPC
+    Ipc(MoicContext),
+}
+
+/// 有任务在等待的事件，及其阻塞队列和分发协程
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+type MoicEventMap = BTreeMap<MoicEvent, (BlockQueue, Arc<Task>)>;
+
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+static MOIC_EVENTS: SpinNoIrq<MoicEventMap> = SpinNoIrq::new(BTreeMap::new());
+
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+impl MoicEvent {
+    /// 创建事件的阻塞队列和分发协程，并将分发协程注册到全局调度器的MOIC中
+    fn start(self) -> (BlockQueue, Arc<Task>) {
+        let queue = BlockQueue::new();
+        let dispatch = MoicDispatch { event: self, queue: queue.share() };
+        // 分发协程被MOIC加入就绪队列、开始运行时，事件已经到来，由`MoicDispatch`的析构唤醒等待的任务
+        let dispatcher = TaskInner::new_async_with_options(async move {
+            drop(dispatch);
+            0
+        }, TaskOptions { name: Some(String::from("moic_event")), ..Default::default() });
+        // 分发协程只由MOIC加入就绪队列
+        dispatcher.set_state(TaskState::Blocked);
+        Processor::with_current(|processor| processor.with_global_scheduler(|scheduler| match self {
+            MoicEvent::ExtIntr(irq) => scheduler.register_ext_intr_handler(irq, dispatcher.clone()),
+            MoicEvent::Ipc(sender) => scheduler.register_receiver(sender, dispatcher.clone()),
+        }));
+        (queue, dispatcher)
+    }
+
+    /// 若事件的阻塞队列仍为`queue`，则将其移除，并撤销分发协程在MOIC中的注册，返回被移除的分发协程
+    /// 之后等待该事件的任务会使用新的阻塞队列和分发协程，而新的注册不会被撤销。
+    fn retire(self, events: &mut MoicEventMap, queue: &BlockQueue) -> Option<Arc<Task>> {
+        if !events.get(&self).is_some_and(|(event_queue, _)| event_queue.same_queue(queue)) {
+            return None;
+        }
+        let (_, dispatcher) = events.remove(&self).unwrap();
+        Processor::with_current(|processor| processor.with_global_scheduler(|scheduler| match self {
+            MoicEvent::ExtIntr(irq) => scheduler.unregister_ext_intr_handler(irq),
+            MoicEvent::Ipc(sender) => scheduler.unregister_receiver(sender),
+        }));
+        Some(dispatcher)
+    }
+}
+
+/// 由分发协程持有，在分发协程运行结束或被终止时析构：撤销事件的注册，并唤醒阻塞队列中的所有任务
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+struct MoicDispatch {
+    event: MoicEvent,
+    queue: BlockQueue,
+}
+
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+impl Drop for MoicDispatch {
+    fn drop(&mut self) {
+        // 被移除的分发协程即为当前的分发协程，其正在结束，无需终止
+        self.event.retire(&mut MOIC_EVENTS.lock(), &self.queue);
+        self.queue.wake_all_to_global();
+    }
+}
+
+/// 队列中的最后一个任务被终止时，终止事件的分发协程
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+impl WaitCancel for SpinNoIrq<MoicEventMap> {
+    fn cancel_wait(&self, queue: &BlockQueue) {
+        let dispatcher = {
+            let mut events = self.lock();
+            let event = events.iter().find(|(_, (event_queue, _))| event_queue.same_queue(queue)).map(|(event, _)| *event);
+            match event {
+                Some(event) if queue.0.lock().is_empty() => event.retire(&mut events, queue),
+                _ => None,
+            }
+        };
+        if let Some(dispatcher) = dispatcher {
+            kill(&dispatcher, 0);
+        }
+    }
+}
+
+/// 将当前任务阻塞在等待`event`的队列中，没有这一队列时创建之
+/// 调用者需随后进行切换，并在等待结束后清除阻塞的记录
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+fn prepare_moic_wait(event: MoicEvent) {
+    let mut events = MOIC_EVENTS.lock();
+    let current = current_ptr();
+    current.clear_wait_site();
+    let (queue, _) = events.entry(event).or_insert_with(|| event.start());
+    // current_state作用域
+    {
+        let mut current_state = current.state_lock();
+        assert!(matches!(*current_state, TaskState::Running));
+        *current_state = TaskState::Blocking;
+    }
+    queue.add_blocked(current, Some(BlockQueue::wait_owner(&MOIC_EVENTS)));
+}
+
+/// 阻塞当前任务，直到外部中断`irq`到来（线程版本）
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub fn wait_ext_intr(irq: usize) {
+    prepare_moic_wait(MoicEvent::ExtIntr(irq));
+    switch_entry(true);
+    current_ptr().clear_wait_site();
+}
+/// 阻塞当前任务，直到外部中断`irq`到来（协程版本）
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub async fn wait_ext_intr_async(irq: usize) {
+    prepare_moic_wait(MoicEvent::ExtIntr(irq));
+    yield_helper().await;
+    current_ptr().clear_wait_site();
+}
+
+/// 外部中断`irq`到来，唤醒等待该中断的任务，需要在外部中断处理函数中调用
+/// 软件模拟的MOIC不能直接接收外部中断，因此只在使用软件模拟的MOIC时提供
+/// 返回值代表是否唤醒了任务
+#[cfg(all(feature = "moic_soft", not(feature = "moic")))]
+pub fn moic_ext_intr(irq: usize) -> bool {
+    Processor::with_current(|processor| processor.with_global_scheduler(|scheduler| scheduler.ext_intr(irq)))
+}
+
+/// 允许当前上下文向`receiver`上下文发送IPC
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub fn register_ipc_sender(receiver: MoicContext) {
+    Processor::with_current(|processor| processor.with_global_scheduler(|scheduler| scheduler.register_sender(receiver)));
+}
+
+/// 阻塞当前任务，直到收到`sender`上下文发送的IPC（线程版本）
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub fn wait_ipc(sender: MoicContext) {
+    prepare_moic_wait(MoicEvent::Ipc(sender));
+    switch_entry(true);
+    current_ptr().clear_wait_site();
+}
+/// 阻塞当前任务，直到收到`sender`上下文发送的IPC（协程版本）
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub async fn wait_ipc_async(sender: MoicContext) {
+    prepare_moic_wait(MoicEvent::Ipc(sender));
+    yield_helper().await;
+    current_ptr().clear_wait_site();
+}
+
+/// 向`receiver`上下文发送IPC，唤醒其中等待当前上下文的IPC的任务
+/// 返回值代表是否唤醒了任务
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub fn send_ipc(receiver: MoicContext) -> bool {
+    Processor::with_current(|processor| processor.with_global_scheduler(|scheduler| scheduler.send_intr(receiver)))
+}
 
 /// 退出任务，可用于函数执行完毕的正常退出或中途退出
 pub fn exit_current(exit_code: i32) {
@@ -300,7 +751,7 @@ pub fn exit_current(exit_code: i32) {
         // current_state作用域
         {
             let mut current_state = current.state_lock();
-            assert!(matches!(*current_state, TaskState::Runable));
+            assert!(matches!(*current_state, TaskState::Running));
             current.set_exit_code(exit_code);
             *current_state = TaskState::Exited; // 状态为Exited的任务一定已经保存好了返回值
         }
@@ -313,7 +764,7 @@ pub async fn exit_current_async(exit_code: i32) {
         // current_state作用域
         {
             let mut current_state = current.state_lock();
-            assert!(matches!(*current_state, TaskState::Runable));
+            assert!(matches!(*current_state, TaskState::Running));
             current.set_exit_code(exit_code);
             *current_state = TaskState::Exited; // 状态为Exited的任务一定已经保存好了返回值
         }
@@ -321,11 +772,65 @@ pub async fn exit_current_async(exit_code: i32) {
     yield_helper().await;
 }
 
-// 目前先不考虑该接口
-// /// 等待另一任务完成，并接收其返回值
-// /// 设想是，在join时，使等待任务获取被等待任务的Arc实例，等到获取了该任务的返回值再释放该实例。
-// pub fn current_join_another(task: Arc<Task>) -> i32
-// pub async fn current_join_another_async(task: Arc<Task>) -> i32
+/// 等待另一任务退出，并接收其返回值（线程版本）
+/// 被等待的任务可以是线程或协程，也可以位于任意调度器中。
+/// 等待期间，当前任务持有被等待任务的Arc实例，因此可以在其退出后读取返回值。
+pub fn join(task: &Arc<Task>) -> i32 {
+    assert!(!Arc::ptr_eq(task, &current_ptr()), "a task cannot join itself!");
+    loop {
+        let need_block = BlockQueue::prepare_block_current(task.join_queue(), |join_queue| join_queue.lock(), |join_queue| {
+            if task.is_exited() { None } else { Some(&mut **join_queue) }
+        });
+        if !need_block {
+            return task.exit_code().unwrap();
+        }
+        switch_entry(true);
+    }
+}
+/// 等待另一任务退出，并接收其返回值（协程版本）
+pub async fn join_async(task: &Arc<Task>) -> i32 {
+    assert!(!Arc::ptr_eq(task, &current_ptr()), "a task cannot join itself!");
+    loop {
+        let need_block = BlockQueue::prepare_block_current(task.join_queue(), |join_queue| join_queue.lock(), |join_queue| {
+            if task.is_exited() { None } else { Some(&mut **join_queue) }
+        });
+        if !need_block {
+            return task.exit_code().unwrap();
+        }
+        yield_helper().await;
+    }
+}
+
+/// 终止另一任务，其返回值设为`exit_code`，等待其退出的任务会被唤醒
+/// 被终止的协程的Future会被析构；被终止的线程（以及被抢占后被终止的协程）的栈会被回收，但其栈上的对象不会被析构，其持有的资源会泄漏。
+/// 因此持有可睡眠锁（`Mutex`、`RwLock`）的线程不会被立即终止，而是在释放所有锁之后的切换时退出。
+/// 阻塞中的任务会在其阻塞队列的锁的保护下被移出队列。
+/// 若任务正在其它CPU上运行，则其会在下一次切换时退出。
+/// 返回值代表任务在此之前是否还未退出。
+pub fn kill(task: &Arc<Task>, exit_code: i32) -> bool {
+    assert!(!task.is_idle() && !task.is_original(), "cannot kill the idle or original task!");
+    assert!(!Arc::ptr_eq(task, &current_ptr()), "use exit_current to end the current task!");
+    match task.mark_killed(exit_code) {
+        KillResult::AlreadyExited => false,
+        KillResult::Deferred => true,
+        KillResult::Exited { was_ready } => {
+            // 就绪的任务若位于当前CPU或全局调度器中，则将其移出；若位于其它CPU的调度器中，则在被取出时跳过。
+            if was_ready {
+                Processor::with_current(|processor| {
+                    if processor.with_local_scheduler(|scheduler| scheduler.remove_task(task)).is_none() {
+                        processor.with_global_scheduler(|scheduler| scheduler.remove_task(task));
+                    }
+                });
+            }
+            BlockQueue::cancel_wait(task);
+            #[cfg(feature = "trace")]
+            trace::record(Processor::current_id(), TraceEvent::Exit, task.id(), 0);
+            Processor::reap_task(task);
+            task.join_queue().lock().wake_all_to_local();
+            true
+        }
+    }
+}
 
 /// 在当前CPU上执行每个tick（时钟中断）执行的、更新调度器状态和判断重调度。
 /// 返回值表示是否需要重调度
@@ -385,13 +890,24 @@ pub fn current_can_preempt() -> bool {
 
 /// 抢占当前任务
 /// 传入的参数为中断时保存的Trap上下文，之后会将其作为任务上下文保存，这样恢复时可以直接恢复到任务中。
-/// 被抢占的任务只能放回当前CPU的局部调度器。
+/// 被抢占的任务放回当前CPU的局部调度器。
 /// 目前，该接口仅为中断处理函数准备。
 #[cfg(feature = "preempt")]
 pub fn preempt_current(task_ctx: &mut TaskContext) {
     Processor::with_current(|processor| {
         let current = processor.current_task().get_current_ptr();
-        assert!(current.is_runable());
+        assert!(current.is_running());
+    });
+    preempt_switch_entry(task_ctx);
+}
+
+/// 抢占当前任务，且将其放回全局调度器，使其可以被其它CPU核心执行
+#[cfg(feature = "preempt")]
+pub fn preempt_current_to_global(task_ctx: &mut TaskContext) {
+    Processor::with_current(|processor| {
+        let current = processor.current_task().get_current_ptr();
+        assert!(current.is_running());
+        current.set_requeue_to_global();
     });
     preempt_switch_entry(task_ctx);
 }
@@ -400,55 +916,70 @@ pub fn preempt_current(task_ctx: &mut TaskContext) {
 
 /// 在任务调度/队列管理模块中，BlockQueue可以配合各种满足trait的任务数据结构；但在向用户暴露的接口中，BlockQueue仅配合Task使用。
 use task_queues::{block_queue, scheduler::{self, BaseScheduler}};
-pub struct BlockQueue(block_queue::BlockQueue<Task>);
+/// 队列内部有锁保护，其方法均只需要共享引用（`&self`），因此不再需要外层的锁。
+/// 阻塞中的任务的记录共享队列内部的任务列表，因此队列可以在有任务阻塞时被移动、替换或释放，但此时其中的任务不会再被唤醒（也不会被释放）。
+pub struct BlockQueue(Arc<SpinNoIrq<block_queue::BlockQueue<Task>>>);
+
+/// 带超时的阻塞的结果
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WaitResult {
+    /// 被阻塞队列的唤醒操作唤醒
+    Woken,
+    /// 超时，此时任务已经离开阻塞队列
+    TimedOut,
+}
 
 impl BlockQueue {
 
     /// 创建阻塞队列
-    /// 不知是否要考虑，用户拿到阻塞队列后，在不正确的时机drop掉，导致其中的任务也被drop掉的问题？
+    /// 队列内部有锁保护，因此超时事件可以直接将任务移出队列。
     pub fn new() -> Self {
         Self {
-            0: block_queue::BlockQueue::new()
+            0: Arc::new(SpinNoIrq::new(block_queue::BlockQueue::new()))
         }
     }
 
     /// 创建一个提供了多线程访问和内部可变性的阻塞队列
+    /// 队列已有内部锁，外层的锁只会重复加锁，因此应使用`Arc::new(BlockQueue::new())`
+    #[deprecated(note = "BlockQueue已有内部锁，请使用Arc::new(BlockQueue::new())")]
     pub fn new_arc() -> Arc<SpinNoIrq<Self>> {
-        Arc::new(SpinNoIrq::new(Self {
-            0: block_queue::BlockQueue::new()
-        }))
+        Arc::new(SpinNoIrq::new(Self::new()))
     }
 
     /// 将当前任务阻塞在该队列上
     /// 与“当前任务管理”中的同名函数功能重复了，不知道要保留哪个，还是全部保留？
-    pub fn block_current(&mut self) {
-        Processor::with_current(|processor| {
+    pub fn block_current(&self) {
+        let current = Processor::with_current(|processor| {
             let current = processor.current_task().get_current_ptr();
             // current_state作用域
             {
                 let mut current_state = current.state_lock();
-                assert!(matches!(*current_state, TaskState::Runable));
+                assert!(matches!(*current_state, TaskState::Running));
                 *current_state = TaskState::Blocking;
             }
-            self.0.add(current);
+            self.add_blocked(current.clone(), None);
+            current
         });
         switch_entry(true);
+        current.clear_wait_site();
     }
 
     /// 将当前任务阻塞在该队列上
     /// 与“当前任务管理”中的同名函数功能重复了，不知道要保留哪个，还是全部保留？
-    pub async fn block_current_async(&mut self) {
-        Processor::with_current(|processor| {
+    pub async fn block_current_async(&self) {
+        let current = Processor::with_current(|processor| {
             let current = processor.current_task().get_current_ptr();
             // current_state作用域
             {
                 let mut current_state = current.state_lock();
-                assert!(matches!(*current_state, TaskState::Runable));
+                assert!(matches!(*current_state, TaskState::Running));
                 *current_state = TaskState::Blocking;
             }
-            self.0.add(current);
+            self.add_blocked(current.clone(), None);
+            current
         });
         yield_helper().await;
+        current.clear_wait_site();
     }
 
     /// 当阻塞队列被锁保护时，请使用该函数进行阻塞
@@ -456,17 +987,19 @@ impl BlockQueue {
     /// （线程版本）
     pub fn block_current_with_locked_self<'a, T, F, U>(locked_self: &'a T, lock_fn: F)
     where F: Fn(&'a T) -> U, U: 'a + DerefMut<Target = Self> + Drop {
-        Processor::with_current(move |processor| {
+        let current = Processor::with_current(move |processor| {
             let current = processor.current_task().get_current_ptr();
             // current_state作用域
             {
                 let mut current_state = current.state_lock();
-                assert!(matches!(*current_state, TaskState::Runable));
+                assert!(matches!(*current_state, TaskState::Running));
                 *current_state = TaskState::Blocking;
             }
-            (*lock_fn(&locked_self)).0.add(current);
+            (*lock_fn(&locked_self)).add_blocked(current.clone(), None);
+            current
         });
         switch_entry(true);
+        current.clear_wait_site();
     }
 
     /// 当阻塞队列被锁保护时，请使用该函数进行阻塞
@@ -474,153 +1007,283 @@ impl BlockQueue {
     /// （协程版本）
     pub async fn block_current_async_with_locked_self<'a, T, F, U>(locked_self: &'a T, lock_fn: F)
     where F: Fn(&'a T) -> U, U: 'a + DerefMut<Target = Self> + Drop {
-        Processor::with_current(move |processor| {
+        let current = Processor::with_current(move |processor| {
             let current = processor.current_task().get_current_ptr();
             // current_state作用域
             {
                 let mut current_state = current.state_lock();
-                assert!(matches!(*current_state, TaskState::Runable));
+                assert!(matches!(*current_state, TaskState::Running));
                 *current_state = TaskState::Blocking;
             }
-            (*lock_fn(&locked_self)).0.add(current);
+            (*lock_fn(&locked_self)).add_blocked(current.clone(), None);
+            current
         });
         yield_helper().await;
+        current.clear_wait_site();
     }
 
-    /// 从队列中唤醒任务，放入当前CPU核心的调度器中
-    /// 根据唤醒的是一个任务还是多个、是否按条件唤醒（条件为真才会唤醒）、唤醒后加入当前CPU调度器还是全局调度器，具有八个版本
-    /// 返回值代表实际唤醒的任务的数量
-    pub fn wake_one_to_local(&mut self) -> usize {
-        let task_option = self.0.wake_one_with_cond(|task| {
-            task.is_blocked()
+    /// 将当前任务阻塞在该队列上，最多等待`duration`时长
+    /// 超时事件在队列的锁的保护下将任务移出队列，因此任务要么被唤醒操作取出，要么被超时事件移出，不会被重复加入调度器。
+    pub fn block_current_timeout(&self, duration: Duration) -> WaitResult {
+        let deadline = timer::current_ticks() + timer::duration_to_ticks(duration);
+        let current = Processor::with_current(|processor| {
+            let current = processor.current_task().get_current_ptr();
+            Self::prepare_timed_block(processor, &current, deadline);
+            self.add_blocked(current.clone(), None);
+            current
         });
-        if let Some(task) = task_option {
-            task.set_state(TaskState::Runable);
-            Processor::with_current(|processor| {
-                processor.add_task_to_local(task)
-            });
-            1
-        }
-        else {
-            0
-        }
+        switch_entry(true);
+        Self::finish_timed_block(&current)
     }
-    pub fn wake_all_to_local(&mut self) -> usize {
-        let tasks = self.0.wake_all_with_cond(|task| {
-            task.is_blocked()
+
+    /// 将当前任务阻塞在该队列上，最多等待`duration`时长（协程版本）
+    pub async fn block_current_timeout_async(&self, duration: Duration) -> WaitResult {
+        let deadline = timer::current_ticks() + timer::duration_to_ticks(duration);
+        let current = Processor::with_current(|processor| {
+            let current = processor.current_task().get_current_ptr();
+            Self::prepare_timed_block(processor, &current, deadline);
+            self.add_blocked(current.clone(), None);
+            current
         });
-        let task_num = tasks.len();
-        if task_num != 0 {
-            for task in &tasks {
-                task.set_state(TaskState::Runable);
-            };
-            Processor::with_current(|processor| {
-                for task in tasks {
-                    processor.add_task_to_local(task)
-                }
-            });
-        }
-        task_num
+        yield_helper().await;
+        Self::finish_timed_block(&current)
     }
-    pub fn wake_one_with_cond_to_local<F>(&mut self, cond: F) -> usize
-    where F: Fn(&Task) -> bool {
-        let task_option = self.0.wake_one_with_cond(|task| {
-            cond(task) && task.is_blocked()
+
+    /// 当阻塞队列被锁保护时，请使用该函数进行带超时的阻塞
+    /// 该函数能够保证任务不会在阻塞期间持有队列的锁
+    /// （线程版本）
+    pub fn block_current_timeout_with_locked_self<'a, T, F, U>(locked_self: &'a T, lock_fn: F, duration: Duration) -> WaitResult
+    where F: Fn(&'a T) -> U, U: 'a + DerefMut<Target = Self> + Drop {
+        let deadline = timer::current_ticks() + timer::duration_to_ticks(duration);
+        let current = Processor::with_current(|processor| {
+            let current = processor.current_task().get_current_ptr();
+            Self::prepare_timed_block(processor, &current, deadline);
+            (*lock_fn(locked_self)).add_blocked(current.clone(), None);
+            current
         });
-        if let Some(task) = task_option {
-            task.set_state(TaskState::Runable);
-            Processor::with_current(|processor| {
-                processor.add_task_to_local(task)
-            });
-            1
-        }
-        else {
-            0
-        }
+        switch_entry(true);
+        Self::finish_timed_block(&current)
     }
-    pub fn wake_all_with_cond_to_local<F>(&mut self, cond: F) -> usize
-    where F: Fn(&Task) -> bool {
-        let tasks = self.0.wake_all_with_cond(|task| {
-            cond(task) && task.is_blocked()
+
+    /// 当阻塞队列被锁保护时，请使用该函数进行带超时的阻塞
+    /// 该函数能够保证任务不会在阻塞期间持有队列的锁
+    /// （协程版本）
+    pub async fn block_current_timeout_async_with_locked_self<'a, T, F, U>(locked_self: &'a T, lock_fn: F, duration: Duration) -> WaitResult
+    where F: Fn(&'a T) -> U, U: 'a + DerefMut<Target = Self> + Drop {
+        let deadline = timer::current_ticks() + timer::duration_to_ticks(duration);
+        let current = Processor::with_current(|processor| {
+            let current = processor.current_task().get_current_ptr();
+            Self::prepare_timed_block(processor, &current, deadline);
+            (*lock_fn(locked_self)).add_blocked(current.clone(), None);
+            current
         });
-        let task_num = tasks.len();
-        if task_num != 0 {
-            for task in &tasks {
-                task.set_state(TaskState::Runable);
-            };
-            Processor::with_current(|processor| {
-                for task in tasks {
-                    processor.add_task_to_local(task)
+        yield_helper().await;
+        Self::finish_timed_block(&current)
+    }
+
+    /// 将当前任务设为Blocking状态，并在当前CPU的定时器列表中加入超时事件
+    /// 超时事件只持有任务的弱引用，因此已经失效的超时事件不会使任务无法释放。
+    fn prepare_timed_block(processor: &Processor, current: &Arc<Task>, deadline: usize) {
+        // current_state作用域
+        {
+            let mut current_state = current.state_lock();
+            assert!(matches!(*current_state, TaskState::Running));
+            *current_state = TaskState::Blocking;
+        }
+        let seq = current.begin_timed_wait();
+        let task = Arc::downgrade(current);
+        processor.with_timer_list(|timer_list| timer_list.add(deadline, Box::new(move || {
+            if let Some(task) = task.upgrade() {
+                Self::time_out(&task, seq);
+            }
+        })));
+    }
+
+    /// 超时事件到期时调用：若序号为`seq`的阻塞仍在进行，则在队列的锁的保护下将任务移出队列并唤醒
+    /// 任务已被唤醒操作取出时，移出失败，超时事件不产生任何效果。
+    fn time_out(task: &Arc<Task>, seq: u64) {
+        let timed_out = {
+            let wait_site = task.wait_site_lock();
+            match &*wait_site {
+                // 持有记录的锁期间，任务无法离开阻塞操作
+                Some(site) if task.is_timed_waiting(seq) => {
+                    site.queue.0.lock().remove(task).is_some() && task.claim_timeout(seq)
                 }
-            });
+                _ => false,
+            }
+        };
+        if timed_out {
+            task.clone().wakeup();
         }
-        task_num
     }
 
-    pub fn wake_one_to_global(&mut self) -> usize {
-        let task_option = self.0.wake_one_with_cond(|task| {
-            task.is_blocked()
-        });
-        if let Some(task) = task_option {
-            task.set_state(TaskState::Runable);
-            Processor::with_current(|processor| {
-                processor.add_task_to_global(task)
-            });
-            1
+    /// 任务被终止时调用，在队列的锁的保护下将其移出阻塞时所在的队列
+    /// 任务可能已被唤醒操作取出、但还未恢复运行，此时将唤醒传递给队列中的其它任务，以免唤醒丢失；同步原语可以通过`WaitCancel`自行处理。
+    /// 需要在任务不再执行、且其资源被回收之前调用，此时同步原语仍被任务借用，因此一定有效。
+    pub(crate) fn cancel_wait(task: &Arc<Task>) {
+        let Some(site) = task.take_wait_site() else {
+            return;
+        };
+        let queue = &site.queue;
+        let unlinked = queue.0.lock().remove(task).is_some();
+        match site.owner {
+            Some(owner) => unsafe { (owner.cancel)(owner.owner, queue) },
+            None if !unlinked => {
+                queue.wake_one_to_local();
+            }
+            None => {}
+        }
+    }
+
+    /// 带超时的阻塞结束后调用，返回阻塞的结果
+    /// 因超时结束时，任务已经被超时事件移出队列
+    fn finish_timed_block(current: &Arc<Task>) -> WaitResult {
+        current.clear_wait_site();
+        if current.finish_timed_wait() {
+            WaitResult::TimedOut
         }
         else {
-            0
+            WaitResult::Woken
         }
     }
-    pub fn wake_all_to_global(&mut self) -> usize {
-        let tasks = self.0.wake_all_with_cond(|task| {
-            task.is_blocked()
-        });
-        let task_num = tasks.len();
-        if task_num != 0 {
-            for task in &tasks {
-                task.set_state(TaskState::Runable);
-            };
-            Processor::with_current(|processor| {
-                for task in tasks {
-                    processor.add_task_to_global(task)
-                }
-            });
+
+    /// 在`lock_fn`获取的锁的保护下，由`select`判断当前任务是否需要阻塞：若其返回了阻塞队列，则将当前任务设为Blocking状态并加入该队列。
+    /// 判断与加入队列在同一临界区内完成，因此不会错过两者之间发生的唤醒。
+    /// 该函数不进行任务切换，返回值代表是否进行了阻塞，若为true，调用者需随后进行切换（`switch_entry`或`yield_helper`）。
+    /// 任务上一次阻塞的记录在同一临界区内清除。
+    pub(crate) fn prepare_block_current<'a, T, L, G, F>(locked: &'a T, lock_fn: L, select: F) -> bool
+    where L: FnOnce(&'a T) -> G, F: FnOnce(&mut G) -> Option<&mut Self> {
+        Self::prepare_block_current_raw(locked, lock_fn, select, None)
+    }
+
+    /// 与`prepare_block_current`相同，但阻塞中的任务被`kill`终止时，由`locked`的`WaitCancel`实现修正同步原语的状态
+    pub(crate) fn prepare_block_current_with_cancel<'a, T, L, G, F>(locked: &'a T, lock_fn: L, select: F) -> bool
+    where T: WaitCancel, L: FnOnce(&'a T) -> G, F: FnOnce(&mut G) -> Option<&mut Self> {
+        Self::prepare_block_current_raw(locked, lock_fn, select, Some(Self::wait_owner(locked)))
+    }
+
+    /// 阻塞时记录的同步原语，`owner`需在任务阻塞期间保持有效
+    fn wait_owner<T: WaitCancel>(owner: &T) -> WaitOwner {
+        unsafe fn cancel<T: WaitCancel>(owner: *const (), queue: &BlockQueue) {
+            (*(owner as *const T)).cancel_wait(queue)
         }
-        task_num
+        WaitOwner { owner: owner as *const T as *const (), cancel: cancel::<T> }
+    }
+
+    fn prepare_block_current_raw<'a, T, L, G, F>(locked: &'a T, lock_fn: L, select: F, owner: Option<WaitOwner>) -> bool
+    where L: FnOnce(&'a T) -> G, F: FnOnce(&mut G) -> Option<&mut Self> {
+        Processor::with_current(move |processor| {
+            let mut guard = lock_fn(locked);
+            let current = processor.current_task().get_current_ptr();
+            current.clear_wait_site();
+            if let Some(block_queue) = select(&mut guard) {
+                // current_state作用域
+                {
+                    let mut current_state = current.state_lock();
+                    assert!(matches!(*current_state, TaskState::Running));
+                    *current_state = TaskState::Blocking;
+                }
+                block_queue.add_blocked(current, owner);
+                true
+            }
+            else {
+                false
+            }
+        })
     }
-    pub fn wake_one_with_cond_to_global<F>(&mut self, cond: F) -> usize
+
+    /// 从队列中唤醒任务，放入当前CPU核心的调度器中
+    /// 根据唤醒的是一个任务还是多个、是否按条件唤醒（条件为真才会唤醒）、唤醒后加入当前CPU调度器还是全局调度器，具有八个版本
+    /// 返回值代表实际唤醒的任务的数量
+    pub fn wake_one_to_local(&self) -> usize {
+        self.wake_one_with_cond_to_local(|_| true)
+    }
+    pub fn wake_all_to_local(&self) -> usize {
+        self.wake_all_with_cond_to_local(|_| true)
+    }
+    pub fn wake_one_with_cond_to_local<F>(&self, cond: F) -> usize
     where F: Fn(&Task) -> bool {
-        let task_option = self.0.wake_one_with_cond(|task| {
-            cond(task) && task.is_blocked()
-        });
-        if let Some(task) = task_option {
-            task.set_state(TaskState::Runable);
-            Processor::with_current(|processor| {
-                processor.add_task_to_global(task)
-            });
-            1
-        }
-        else {
-            0
-        }
+        let task = self.0.lock().wake_one_with_cond(cond);
+        Self::wake_tasks(task, false)
     }
-    pub fn wake_all_with_cond_to_global<F>(&mut self, cond: F) -> usize
+    pub fn wake_all_with_cond_to_local<F>(&self, cond: F) -> usize
     where F: Fn(&Task) -> bool {
-        let tasks = self.0.wake_all_with_cond(|task| {
-            cond(task) && task.is_blocked()
-        });
-        let task_num = tasks.len();
-        if task_num != 0 {
-            for task in &tasks {
-                task.set_state(TaskState::Runable);
-            };
-            Processor::with_current(|processor| {
-                for task in tasks {
-                    processor.add_task_to_global(task)
-                }
-            });
+        let tasks = self.0.lock().wake_all_with_cond(cond);
+        Self::wake_tasks(tasks, false)
+    }
+
+    pub fn wake_one_to_global(&self) -> usize {
+        self.wake_one_with_cond_to_global(|_| true)
+    }
+    pub fn wake_all_to_global(&self) -> usize {
+        self.wake_all_with_cond_to_global(|_| true)
+    }
+    pub fn wake_one_with_cond_to_global<F>(&self, cond: F) -> usize
+    where F: Fn(&Task) -> bool {
+        let task = self.0.lock().wake_one_with_cond(cond);
+        Self::wake_tasks(task, true)
+    }
+    pub fn wake_all_with_cond_to_global<F>(&self, cond: F) -> usize
+    where F: Fn(&Task) -> bool {
+        let tasks = self.0.lock().wake_all_with_cond(cond);
+        Self::wake_tasks(tasks, true)
+    }
+
+    /// 共享同一任务列表的队列，用于在阻塞的记录中引用队列
+    pub(crate) fn share(&self) -> Self {
+        Self(self.0.clone())
+    }
+
+    /// 两者是否为同一队列（或其共享引用）
+    pub(crate) fn same_queue(&self, other: &BlockQueue) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// 队列的地址，用于记录任务等待的对象
+    pub(crate) fn addr(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+
+    /// 将当前任务（处于Blocking状态）加入队列，并记录其等待的队列
+    fn add_blocked(&self, task: Arc<Task>, owner: Option<WaitOwner>) {
+        task.set_wait_site(self, owner);
+        self.0.lock().add(task);
+    }
+
+    /// 唤醒从队列中取出的任务
+    /// 队列中的任务可能处于Blocking状态（已加入队列但还未完成切换），此时仅修改状态，由切换过程将其放回调度器，从而避免丢失唤醒。
+    /// 已经超时的任务已被超时事件移出队列，不会出现在这里。
+    fn wake_tasks<I>(tasks: I, to_global: bool) -> usize
+    where I: IntoIterator<Item = Arc<Task>> {
+        let mut task_num = 0;
+        for task in tasks {
+            if task.wakeup_to(to_global) {
+                task_num += 1;
+            }
         }
         task_num
     }
-}
\ No newline at end of file
+}
+
+/// 拥有阻塞队列的同步原语
+/// 在其中阻塞的任务被`kill`终止时，需要修正原语的状态（如等待者的数量），并将任务可能已经收到的唤醒传递给其它任务。
+pub(crate) trait WaitCancel {
+    /// 被终止的任务已经离开`queue`，调用时不持有队列的锁
+    fn cancel_wait(&self, queue: &BlockQueue);
+}
+
+/// 阻塞操作的作用域，离开时清除任务阻塞时所在队列的记录
+/// 用于可能不经过`prepare_block_current`就结束的阻塞循环，使记录不会比阻塞队列存在得更久
+pub(crate) struct WaitScope(Arc<Task>);
+
+impl WaitScope {
+    pub(crate) fn new() -> Self {
+        Self(current_ptr())
+    }
+}
+
+impl Drop for WaitScope {
+    fn drop(&mut self) {
+        self.0.clear_wait_site();
+    }
+}
@@ -0,0 +1,94 @@
+//! 基于硬件性能计数器（cycle、instret）的按任务统计
+//! 需要M态软件（如SBI）在`mcounteren`中允许S态读取这两个计数器
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use riscv::register::{cycle, instret};
+
+/// 任务的硬件性能计数器统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HpmStats {
+    /// 任务运行期间经过的时钟周期数
+    pub cycles: u64,
+    /// 任务运行期间退休的指令数
+    pub instret: u64,
+}
+
+#[inline]
+fn read_counters() -> (u64, u64) {
+    (cycle::read() as u64, instret::read() as u64)
+}
+
+/// 每个任务持有的计数器记录
+/// 任务开始运行时记录计数器的值，停止运行时将差值累加到任务上
+pub(crate) struct HpmAccount {
+    /// 任务开始运行时计数器的值，为0代表未在运行
+    since_cycle: AtomicU64,
+    since_instret: AtomicU64,
+    /// 被抢占时，在进入切换过程前记录的计数器的值，从而不将切换过程计入任务。为0代表未记录
+    stop_cycle: AtomicU64,
+    stop_instret: AtomicU64,
+    cycles: AtomicU64,
+    instret: AtomicU64,
+}
+
+impl HpmAccount {
+    pub(crate) const fn new() -> Self {
+        Self {
+            since_cycle: AtomicU64::new(0),
+            since_instret: AtomicU64::new(0),
+            stop_cycle: AtomicU64::new(0),
+            stop_instret: AtomicU64::new(0),
+            cycles: AtomicU64::new(0),
+            instret: AtomicU64::new(0),
+        }
+    }
+
+    /// 在抢占入口处调用，记录任务停止运行时计数器的值
+    pub(crate) fn mark_stop(&self) {
+        let (cycle, instret) = read_counters();
+        self.stop_cycle.store(cycle, Ordering::Release);
+        self.stop_instret.store(instret, Ordering::Release);
+    }
+
+    /// 任务没有被切换出去（继续运行），丢弃抢占入口处记录的值
+    pub(crate) fn cancel_stop(&self) {
+        self.stop_cycle.store(0, Ordering::Release);
+        self.stop_instret.store(0, Ordering::Release);
+    }
+
+    pub(crate) fn switch_in(&self) {
+        let (cycle, instret) = read_counters();
+        self.since_cycle.store(cycle, Ordering::Release);
+        self.since_instret.store(instret, Ordering::Release);
+    }
+
+    pub(crate) fn switch_out(&self) {
+        let (now_cycle, now_instret) = read_counters();
+        let stop_cycle = self.stop_cycle.swap(0, Ordering::AcqRel);
+        let stop_instret = self.stop_instret.swap(0, Ordering::AcqRel);
+        let end_cycle = if stop_cycle != 0 { stop_cycle } else { now_cycle };
+        let end_instret = if stop_instret != 0 { stop_instret } else { now_instret };
+        // original任务在创建前就已经在运行，不统计其第一次运行
+        let since_cycle = self.since_cycle.swap(0, Ordering::AcqRel);
+        let since_instret = self.since_instret.swap(0, Ordering::AcqRel);
+        if since_cycle != 0 {
+            self.cycles.fetch_add(end_cycle.wrapping_sub(since_cycle), Ordering::AcqRel);
+            self.instret.fetch_add(end_instret.wrapping_sub(since_instret), Ordering::AcqRel);
+        }
+    }
+
+    /// 包含正在进行的一次运行
+    pub(crate) fn stats(&self) -> HpmStats {
+        let (now_cycle, now_instret) = read_counters();
+        let mut stats = HpmStats {
+            cycles: self.cycles.load(Ordering::Acquire),
+            instret: self.instret.load(Ordering::Acquire),
+        };
+        let since_cycle = self.since_cycle.load(Ordering::Acquire);
+        if since_cycle != 0 {
+            stats.cycles += now_cycle.wrapping_sub(since_cycle);
+            stats.instret += now_instret.wrapping_sub(self.since_instret.load(Ordering::Acquire));
+        }
+        stats
+    }
+}
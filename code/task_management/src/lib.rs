@@ -9,5 +9,13 @@ mod api;
 mod processor;
 mod task;
 mod stack;
+mod timer;
+mod registry;
+mod task_local;
+#[cfg(feature = "hpm")]
+mod hpm;
+#[cfg(feature = "trace")]
+mod trace;
+pub mod sync;
 
 pub use api::*;
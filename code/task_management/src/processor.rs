@@ -1,13 +1,15 @@
-use core::{cell::UnsafeCell, sync::atomic::Ordering};
+use core::{cell::UnsafeCell, sync::atomic::{AtomicU64, AtomicUsize, Ordering}, time::Duration};
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use kernel_guard::{IrqSave, NoPreemptIrqSave};
 use lazy_init::LazyInit;
 use spinlock::{SpinNoIrq, SpinNoIrqGuard, SpinNoIrqOnly};
 use task_queues::scheduler::{self, BaseScheduler};
 use core::sync::atomic::AtomicBool;
 
-use crate::{stack::StackPool, task::{TaskContext, TaskInner}, Task};
+#[cfg(feature = "trace")]
+use crate::trace::{self, TraceEvent};
+use crate::{stack::StackPool, task::{TaskContext, TaskInner}, timer::{self, TimerList}, Task};
 
 #[cfg(feature = "smp")]
 #[percpu::def_percpu]
@@ -16,8 +18,35 @@ static PROCESSOR: LazyInit<SpinNoIrqOnly<Processor>> = LazyInit::new();
 #[cfg(not(feature = "smp"))]
 static PROCESSOR: LazyInit<SpinNoIrqOnly<Processor>> = LazyInit::new();
 
+/// 当前CPU的id，用于在不获取Processor锁的情况下得到当前CPU
+#[cfg(all(feature = "trace", feature = "smp"))]
+#[percpu::def_percpu]
+static CPU_ID: usize = 0;
+
+#[cfg(all(feature = "trace", not(feature = "smp")))]
+static CPU_ID: AtomicUsize = AtomicUsize::new(0);
+
 static GLOBAL_SCHEDULER: LazyInit<Arc<SpinNoIrqOnly<Scheduler>>> = LazyInit::new();
 
+/// 各CPU的收件箱，用于将任务加入其它CPU的局部调度器
+/// 局部调度器只能由其所属的CPU访问，因此其它CPU先将任务放入收件箱，再由所属CPU在选取任务时移入局部调度器。
+static REMOTE_INBOXES: LazyInit<Vec<SpinNoIrqOnly<Vec<Arc<Task>>>>> = LazyInit::new();
+
+/// 各CPU的空闲与忙碌时间，可由其它CPU读取
+static CPU_TIMES: LazyInit<Vec<CpuTimes>> = LazyInit::new();
+
+/// CPU的数量，在初始化主CPU时设置
+static CPU_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// 由使用该模块的系统实现，向其它CPU发送处理器间中断
+/// 任务被放入其它CPU的收件箱后调用，使空闲时等待中断（如执行`wfi`）的CPU及时取出任务；收到中断的CPU只需返回，之后的调度会处理收件箱。
+/// 调用时持有当前CPU的Processor锁，因此其中不能使用任务管理的接口。
+#[cfg(feature = "ipi")]
+#[crate_interface::def_interface]
+pub trait IpiIf {
+    fn send_ipi(cpu_id: usize);
+}
+
 #[cfg(feature = "smp")]
 static MAIN_PROCESSOR_INIT_FINISHED: AtomicBool = AtomicBool::new(false);
 
@@ -40,6 +69,9 @@ pub(crate) struct Processor {
     stack_pool: UnsafeCell<StackPool>,
     // current_stack: CurrentStack, // 这里要仔细读一下任务切换里栈的行为
 
+    /// 定时器列表，存放该CPU上的睡眠、超时等事件
+    timer_list: UnsafeCell<TimerList>,
+
     /// 空闲时执行的任务
     idle_task: Arc<Task>,
 
@@ -115,6 +147,13 @@ impl Processor {
         }
     }
 
+    // 注意：不要同时申请多个mut引用。
+    #[inline]
+    pub(crate) fn with_timer_list<F, T>(&self, f: F) -> T
+    where F: FnOnce(&mut TimerList) -> T {
+        unsafe { f(&mut *self.timer_list.get()) }
+    }
+
     #[inline]
     pub(crate) fn get_stack_pool_mut(&self) -> &mut StackPool {
         unsafe {
@@ -128,6 +167,15 @@ impl Processor {
     /// 使用percpu库初始化静态变量
     /// 只包含了初始化CPU和调度器的过程，不包含运行main任务
     pub(crate) fn init_main_processor(cpu_id: usize, cpu_num: usize) {
+        // 任务的CPU亲和性以u64位图表示
+        assert!(cpu_num <= u64::BITS as usize, "at most {} cpus are supported!", u64::BITS);
+        CPU_NUM.store(cpu_num, Ordering::Release);
+        REMOTE_INBOXES.init_by((0 .. cpu_num).map(|_| SpinNoIrqOnly::new(Vec::new())).collect());
+        CPU_TIMES.init_by((0 .. cpu_num).map(|_| CpuTimes::new()).collect());
+        #[cfg(feature = "trace")]
+        trace::init(cpu_num);
+        #[cfg(feature = "stack_stats")]
+        crate::stack::init_stats(cpu_num);
         GLOBAL_SCHEDULER.init_by(Arc::new(SpinNoIrqOnly::new(Scheduler::new())));
         GLOBAL_SCHEDULER.lock().init();
 
@@ -156,6 +204,35 @@ impl Processor {
         });
     }
 
+    pub(crate) fn cpu_num() -> usize {
+        CPU_NUM.load(Ordering::Acquire)
+    }
+
+    /// 不获取Processor锁，得到当前CPU的id
+    /// 可以在持有Processor锁时调用
+    #[cfg(feature = "trace")]
+    pub(crate) fn current_id() -> usize {
+        #[cfg(feature = "smp")]
+        {
+            CPU_ID.with_current(|cpu_id| *cpu_id)
+        }
+
+        #[cfg(not(feature = "smp"))]
+        {
+            CPU_ID.load(Ordering::Acquire)
+        }
+    }
+
+    /// 在任务切换时，将上一段时间计入当前CPU的空闲或忙碌时间
+    pub(crate) fn account_cpu_time(&self, now: u64, prev_is_idle: bool, next_is_idle: bool) {
+        CPU_TIMES[self.id].account(now, prev_is_idle, next_is_idle);
+    }
+
+    /// id为`cpu_id`的CPU的空闲与忙碌时间
+    pub(crate) fn cpu_stats(cpu_id: usize) -> Option<CpuStats> {
+        CPU_TIMES.get(cpu_id).map(|cpu_times| cpu_times.stats(timer::current_ticks() as u64))
+    }
+
     pub(crate) fn current_is_init() -> bool {
         #[cfg(feature = "smp")]
         let is_init = PROCESSOR.with_current(|processor| {
@@ -168,6 +245,21 @@ impl Processor {
         is_init
     }
 
+    /// 回收已退出任务的资源，将其栈放回当前CPU的栈池
+    /// 不能在持有Processor锁时调用
+    pub(crate) fn reap_task(task: &Task) {
+        if let Some(stack) = task.reap() {
+            #[cfg(feature = "stack_stats")]
+            {
+                let peak = task.record_stack_peak(stack.peak_usage());
+                axlog::debug!("task {} exited, stack peak usage: {:#x} / {:#x}", task.id(), peak, stack.size());
+            }
+            Self::with_current(|processor| unsafe {
+                processor.get_stack_pool_mut().recycle_stack(stack);
+            });
+        }
+    }
+
     /// 获取当前CPU
     /// 需要在当前核心执行了初始化函数之后调用
     pub(crate) fn with_current<F, T>(f: F) -> T
@@ -199,7 +291,12 @@ impl Processor {
 
     // 只负责加入队列，不负责更改任务状态
     // 应在任务状态更改完成后，再调用该函数
+    // 若任务的亲和性不允许其在当前CPU上运行，则改为加入允许其运行的CPU的收件箱
     pub(crate) fn add_task_to_local(&self, task: Arc<Task>) {
+        if !task.allows_cpu(self.id) {
+            self.migrate_task(task);
+            return;
+        }
         self.with_local_scheduler(|scheduler| {
             scheduler.add_task(task);
         })
@@ -213,27 +310,49 @@ impl Processor {
         })
     }
 
-    /// 选取并从调度器中取出最高优先级的任务
-    pub(crate) fn pick_next_task(&self) -> Arc<Task> {
-        let local_priority = self.with_local_scheduler(|scheduler| { scheduler.highest_priority() });
-        let global_priority = self.with_global_scheduler(|scheduler| { scheduler.highest_priority() });
-
-        let scheduler_task = if local_priority <= global_priority {
-            // 从本地调度器取任务
-            self.with_local_scheduler(|scheduler| { scheduler.pick_next_task() })
+    // 只负责加入队列，不负责更改任务状态
+    // 应在任务状态更改完成后，再调用该函数
+    // 加入id为`cpu_id`的CPU的局部调度器
+    pub(crate) fn add_task_to_remote(&self, cpu_id: usize, task: Arc<Task>) {
+        if cpu_id == self.id {
+            self.add_task_to_local(task);
         }
         else {
-            // 从全局调度器取任务
-            self.with_global_scheduler(|scheduler| { scheduler.pick_next_task() })
-        };
+            Self::push_to_inbox(cpu_id, task);
+        }
+    }
 
-        // 没有任务的队列优先级为N，而有任务的队列优先级最低也为N-1。
-        // 因此，如果较低优先级的队列没有任务，则另一个队列也一定没有任务。
-        if let Some(task) = scheduler_task {
-            task
+    /// 选取并从调度器中取出最高优先级的任务
+    /// 取出的任务被设为Running状态。
+    /// 亲和性不允许在当前CPU上运行的任务不会放回原调度器，而是直接转移到允许其运行的CPU的收件箱，因此不会被反复取出，也不会改变其它任务的先后顺序。
+    pub(crate) fn pick_next_task(&self) -> Arc<Task> {
+        // 先将其它CPU放入收件箱的任务移入局部调度器
+        let remote_tasks = core::mem::take(&mut *REMOTE_INBOXES[self.id].lock());
+        for task in remote_tasks {
+            self.add_task_to_local(task);
         }
-        else {
-            self.idle_task.clone()
+
+        loop {
+            let local_priority = self.with_local_scheduler(|scheduler| { scheduler.highest_priority() });
+            let global_priority = self.with_global_scheduler(|scheduler| { scheduler.highest_priority() });
+
+            let scheduler_task = if local_priority <= global_priority {
+                // 从本地调度器取任务
+                self.with_local_scheduler(|scheduler| { scheduler.pick_next_task() })
+            }
+            else {
+                // 从全局调度器取任务
+                self.with_global_scheduler(|scheduler| { scheduler.pick_next_task() })
+            };
+
+            // 没有任务的队列优先级为N，而有任务的队列优先级最低也为N-1。
+            // 因此，如果较低优先级的队列没有任务，则另一个队列也一定没有任务。
+            match scheduler_task {
+                // 在就绪期间被终止的任务，其资源已经在`kill`中回收，此处只需将其丢弃
+                Some(task) if task.allows_cpu(self.id) => if task.claim_run() { break task },
+                Some(task) => self.migrate_task(task),
+                None => break self.idle_task.clone(),
+            }
         }
     }
 
@@ -250,8 +369,35 @@ impl Processor {
 
 /// private方法
 impl Processor {
+    /// 将亲和性不允许在当前CPU上运行的任务转移到允许其运行的CPU的收件箱
+    /// 优先选择任务上一次运行的CPU；亲和性不包含任何存在的CPU时，放入全局调度器。
+    fn migrate_task(&self, task: Arc<Task>) {
+        #[cfg(feature = "trace")]
+        trace::record(self.id, TraceEvent::Migrate, task.id(), 0);
+        let cpu_num = Self::cpu_num();
+        let target = task.last_cpu()
+            .filter(|&cpu_id| cpu_id < cpu_num && task.allows_cpu(cpu_id))
+            .or_else(|| (0 .. cpu_num).find(|&cpu_id| task.allows_cpu(cpu_id)));
+        match target {
+            Some(cpu_id) => Self::push_to_inbox(cpu_id, task),
+            None => self.add_task_to_global(task),
+        }
+    }
+
+    /// 将任务放入id为`cpu_id`的CPU的收件箱，并通知该CPU
+    fn push_to_inbox(cpu_id: usize, task: Arc<Task>) {
+        REMOTE_INBOXES[cpu_id].lock().push(task);
+        #[cfg(feature = "ipi")]
+        crate_interface::call_interface!(IpiIf::send_ipi(cpu_id));
+    }
+
     // 需要在GLOBAL_SCHEDULER初始化完成后调用
     fn new(id: usize) -> Self {
+        CPU_TIMES[id].start(timer::current_ticks() as u64);
+        #[cfg(all(feature = "trace", feature = "smp"))]
+        CPU_ID.with_current(|cpu_id| *cpu_id = id);
+        #[cfg(all(feature = "trace", not(feature = "smp")))]
+        CPU_ID.store(id, Ordering::Release);
         let idle_task = TaskInner::new_idle(); // idle_task不需放入调度器，调度器如果取不到任务就会返回idle_task
         let original_task = TaskInner::new_original(); // 运行任务前，处理器的上下文也视为一个任务，即为original_task
         let processor = Self {
@@ -259,7 +405,8 @@ impl Processor {
             local_scheduler: UnsafeCell::new(Scheduler::new()),
             global_scheduler: GLOBAL_SCHEDULER.try_get().unwrap().clone(),
             current_task: UnsafeCell::new(CurrentTask::new(original_task.clone())),
-            stack_pool: UnsafeCell::new(StackPool::new()),
+            stack_pool: UnsafeCell::new(StackPool::new(id)),
+            timer_list: UnsafeCell::new(TimerList::new()),
             idle_task,
             original_task,
             switch_guard: UnsafeCell::new(None),
@@ -269,4 +416,65 @@ impl Processor {
         }
         processor
     }
-}
\ No newline at end of file
+}
+/// CPU的空闲与忙碌时间（time寄存器的tick数）
+/// 执行idle任务的时间计为空闲，其余（包括任务切换的开销）计为忙碌
+struct CpuTimes {
+    idle_ticks: AtomicU64,
+    busy_ticks: AtomicU64,
+    /// 最近一次切换任务时time寄存器的值
+    switched_at: AtomicU64,
+    /// 当前是否在执行idle任务
+    in_idle: AtomicBool,
+}
+
+impl CpuTimes {
+    fn new() -> Self {
+        Self {
+            idle_ticks: AtomicU64::new(0),
+            busy_ticks: AtomicU64::new(0),
+            switched_at: AtomicU64::new(0),
+            in_idle: AtomicBool::new(false),
+        }
+    }
+
+    fn start(&self, now: u64) {
+        self.switched_at.store(now, Ordering::Release);
+    }
+
+    fn account(&self, now: u64, prev_is_idle: bool, next_is_idle: bool) {
+        let elapsed = now.saturating_sub(self.switched_at.swap(now, Ordering::AcqRel));
+        if prev_is_idle {
+            self.idle_ticks.fetch_add(elapsed, Ordering::AcqRel);
+        }
+        else {
+            self.busy_ticks.fetch_add(elapsed, Ordering::AcqRel);
+        }
+        self.in_idle.store(next_is_idle, Ordering::Release);
+    }
+
+    /// 包含从最近一次切换到`now`的时间
+    fn stats(&self, now: u64) -> CpuStats {
+        let running = now.saturating_sub(self.switched_at.load(Ordering::Acquire));
+        let (mut idle, mut busy) = (self.idle_ticks.load(Ordering::Acquire), self.busy_ticks.load(Ordering::Acquire));
+        if self.in_idle.load(Ordering::Acquire) {
+            idle += running;
+        }
+        else {
+            busy += running;
+        }
+        CpuStats {
+            idle: timer::ticks_to_duration(idle),
+            busy: timer::ticks_to_duration(busy),
+        }
+    }
+}
+
+/// CPU的空闲与忙碌时间统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuStats {
+    /// 执行idle任务的时间
+    pub idle: Duration,
+    /// 执行其它任务以及任务切换的时间
+    pub busy: Duration,
+}
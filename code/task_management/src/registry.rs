@@ -0,0 +1,101 @@
+//! 所有任务的注册表，用于遍历、查找任务以及输出任务快照
+
+use core::fmt::{self, Write};
+use alloc::{collections::BTreeMap, sync::{Arc, Weak}, vec::Vec};
+use spinlock::SpinNoIrq;
+
+use crate::{task::{Task, TaskKind}, timer};
+
+/// 以任务id为键，存储所有存在的任务的弱引用
+/// 任务在创建时加入，在其最后一个引用被释放时移除
+static REGISTRY: SpinNoIrq<BTreeMap<u64, Weak<Task>>> = SpinNoIrq::new(BTreeMap::new());
+
+pub(crate) fn register(task: &Arc<Task>) {
+    REGISTRY.lock().insert(task.id(), Arc::downgrade(task));
+}
+
+pub(crate) fn unregister(id: u64) {
+    REGISTRY.lock().remove(&id);
+}
+
+/// 按id顺序对每个任务执行`f`
+/// 先在锁内取得所有任务的引用，再在锁外执行`f`，因此`f`中可以创建或释放任务
+pub(crate) fn for_each_task<F>(mut f: F)
+where F: FnMut(&Arc<Task>) {
+    let tasks: Vec<Arc<Task>> = REGISTRY.lock().values().filter_map(Weak::upgrade).collect();
+    for task in &tasks {
+        f(task);
+    }
+}
+
+pub(crate) fn lookup(id: u64) -> Option<Arc<Task>> {
+    REGISTRY.lock().get(&id).and_then(Weak::upgrade)
+}
+
+/// 以JSON Lines格式输出所有任务的快照，每行一个任务
+/// 运行时间以time寄存器的tick数输出，使输出不依赖于时钟频率的初始化
+/// 可能在panic处理函数中调用，此时锁可能被panic的执行流持有，因此只尝试获取锁：注册表被占用时输出一行占位，任务状态被占用时以`"<locked>"`代替
+pub(crate) fn dump_tasks<W: Write>(w: &mut W) -> fmt::Result {
+    let tasks: Vec<Arc<Task>> = match REGISTRY.try_lock() {
+        Some(registry) => registry.values().filter_map(Weak::upgrade).collect(),
+        None => return w.write_str("{\"registry\":\"<locked>\"}\n"),
+    };
+    for task in &tasks {
+        dump_task(w, task)?;
+    }
+    Ok(())
+}
+
+fn dump_task<W: Write>(w: &mut W, task: &Task) -> fmt::Result {
+    write!(w, "{{\"id\":{},\"name\":", task.id())?;
+    match task.name() {
+        Some(name) => write_json_str(w, name)?,
+        None => w.write_str("null")?,
+    }
+    let kind = match task.kind() {
+        TaskKind::Thread => "thread",
+        TaskKind::Coroutine => "coroutine",
+    };
+    let state = task.try_state();
+    write!(w, ",\"kind\":\"{}\",\"state\":", kind)?;
+    match state {
+        Some(state) => write!(w, "\"{:?}\"", state)?,
+        None => w.write_str("\"<locked>\"")?,
+    }
+    w.write_str(",\"cpu\":")?;
+    write_json_opt(w, task.last_cpu())?;
+    w.write_str(",\"priority\":")?;
+    write_json_opt(w, task.priority())?;
+    w.write_str(",\"exit_code\":")?;
+    write_json_opt(w, state.and_then(|state| task.exit_code_in(state)))?;
+    write!(w, ",\"runtime_ticks\":{}", task.runtime_ticks(timer::current_ticks() as u64))?;
+    w.write_str(",\"waiting_on\":")?;
+    match task.waiting_on() {
+        Some(addr) => write!(w, "\"{:#x}\"", addr)?,
+        None => w.write_str("null")?,
+    }
+    w.write_str("}\n")
+}
+
+fn write_json_opt<W: Write, T: fmt::Display>(w: &mut W, value: Option<T>) -> fmt::Result {
+    match value {
+        Some(value) => write!(w, "{}", value),
+        None => w.write_str("null"),
+    }
+}
+
+fn write_json_str<W: Write>(w: &mut W, s: &str) -> fmt::Result {
+    w.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(w, "\\u{:04x}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
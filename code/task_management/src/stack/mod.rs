@@ -1,5 +1,11 @@
 mod stack_pool;
 mod task_stack;
 
-pub(crate) use task_stack::TaskStack;
-pub(crate) use stack_pool::StackPool;
\ No newline at end of file
+pub(crate) use task_stack::{TaskStack, MIN_TASK_STACK_SIZE, TASK_STACK_SIZE, TASK_STACK_SIZE_ALIGN};
+pub(crate) use stack_pool::StackPool;
+#[cfg(feature = "stack_stats")]
+pub use stack_pool::{CpuStackPoolStats, StackPoolStats};
+#[cfg(feature = "stack_stats")]
+pub(crate) use stack_pool::init_stats;
+#[cfg(feature = "stack_guard_page")]
+pub use task_stack::StackGuardPage;
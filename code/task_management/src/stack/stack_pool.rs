@@ -1,25 +1,150 @@
-use alloc::sync::Arc;
+use alloc::{collections::BTreeMap, sync::Arc};
+#[cfg(feature = "stack_stats")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "stack_stats")]
+use lazy_init::LazyInit;
 use super::TaskStack;
+
+/// 所有CPU的栈池中缓存的空闲栈的数量
+#[cfg(feature = "stack_stats")]
+static CACHED_STACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// 各CPU的栈池的计数，可由其它CPU读取
+#[cfg(feature = "stack_stats")]
+static CPU_POOL_COUNTERS: LazyInit<Vec<CpuPoolCounters>> = LazyInit::new();
+
+#[cfg(feature = "stack_stats")]
+#[derive(Default)]
+struct CpuPoolCounters {
+    allocated: AtomicUsize,
+    reused: AtomicUsize,
+    recycled: AtomicUsize,
+    cached: AtomicUsize,
+}
+
+#[cfg(feature = "stack_stats")]
+pub(crate) fn init_stats(cpu_num: usize) {
+    CPU_POOL_COUNTERS.init_by((0 .. cpu_num).map(|_| CpuPoolCounters::default()).collect());
+}
+
+/// 栈池的统计信息
+#[cfg(feature = "stack_stats")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StackPoolStats {
+    /// 已分配的栈的总数
+    pub allocated: usize,
+    /// 栈池中缓存的空闲栈的数量
+    pub cached: usize,
+    /// 正在被任务或CPU使用的栈的数量
+    pub in_use: usize,
+}
+
+#[cfg(feature = "stack_stats")]
+impl StackPoolStats {
+    /// 所有CPU的栈池的统计信息
+    pub(crate) fn global() -> Self {
+        let allocated = super::task_stack::ALLOCATED_STACKS.load(Ordering::Relaxed);
+        let cached = CACHED_STACKS.load(Ordering::Relaxed);
+        Self {
+            allocated,
+            cached,
+            in_use: allocated.saturating_sub(cached),
+        }
+    }
+}
+
+/// 单个CPU的栈池的统计信息
+/// 栈可能在一个CPU上分配、在另一个CPU上回收，因此各CPU的`allocated`与`cached`之差不代表该CPU正在使用的栈的数量
+#[cfg(feature = "stack_stats")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuStackPoolStats {
+    /// 该CPU的栈池新分配的栈的数量
+    pub allocated: usize,
+    /// 从该CPU的栈池中取出缓存的栈的次数
+    pub reused: usize,
+    /// 回收到该CPU的栈池中的栈的数量
+    pub recycled: usize,
+    /// 该CPU的栈池中缓存的空闲栈的数量
+    pub cached: usize,
+}
+
+#[cfg(feature = "stack_stats")]
+impl CpuStackPoolStats {
+    /// id为`cpu_id`的CPU的栈池的统计信息，`cpu_id`无效时返回None
+    pub(crate) fn of(cpu_id: usize) -> Option<Self> {
+        let counters = CPU_POOL_COUNTERS.try_get()?.get(cpu_id)?;
+        Some(Self {
+            allocated: counters.allocated.load(Ordering::Relaxed),
+            reused: counters.reused.load(Ordering::Relaxed),
+            recycled: counters.recycled.load(Ordering::Relaxed),
+            cached: counters.cached.load(Ordering::Relaxed),
+        })
+    }
+}
 use alloc::vec::Vec;
 
 /// A simple stack pool
 pub(crate) struct StackPool {
+    /// 所属的CPU，用于更新该CPU的统计信息
+    #[cfg(feature = "stack_stats")]
+    cpu_id: usize,
     curr_stack: Option<Arc<TaskStack>>,
-    free_stacks: Vec<Arc<TaskStack>>,
+    /// 按栈大小分类的空闲栈
+    free_stacks: BTreeMap<usize, Vec<Arc<TaskStack>>>,
 }
 
 impl StackPool {
     /// Creates a new empty stack pool.
-    pub(crate) const fn new() -> Self {
+    pub(crate) const fn new(_cpu_id: usize) -> Self {
         Self {
+            #[cfg(feature = "stack_stats")]
+            cpu_id: _cpu_id,
             curr_stack: None,
-            free_stacks: Vec::new(),
+            free_stacks: BTreeMap::new(),
         }
     }
 
-    /// Fetch a free stack from the pool.
-    pub(crate) fn fetch(&mut self) -> Arc<TaskStack> {
-        self.free_stacks.pop().unwrap_or_else(|| Arc::new(TaskStack::alloc()))
+    /// Fetch a free stack of the given size from the pool.
+    pub(crate) fn fetch(&mut self, size: usize) -> Arc<TaskStack> {
+        let stack = match self.free_stacks.get_mut(&size).and_then(|stacks| stacks.pop()) {
+            Some(stack) => {
+                #[cfg(feature = "stack_stats")]
+                {
+                    CACHED_STACKS.fetch_sub(1, Ordering::Relaxed);
+                    let counters = &CPU_POOL_COUNTERS[self.cpu_id];
+                    counters.cached.fetch_sub(1, Ordering::Relaxed);
+                    counters.reused.fetch_add(1, Ordering::Relaxed);
+                }
+                stack
+            }
+            None => {
+                #[cfg(feature = "stack_stats")]
+                CPU_POOL_COUNTERS[self.cpu_id].allocated.fetch_add(1, Ordering::Relaxed);
+                Arc::new(TaskStack::alloc(size))
+            }
+        };
+        #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+        stack.scrub_if_dirty();
+        stack
+    }
+
+    /// Size of the current stack.
+    pub(crate) fn curr_stack_size(&self) -> Option<usize> {
+        self.curr_stack.as_ref().map(|stack| stack.size())
+    }
+
+    /// 当前栈的最大使用量
+    #[cfg(feature = "stack_stats")]
+    pub(crate) fn curr_stack_peak_usage(&self) -> Option<usize> {
+        self.curr_stack.as_ref().map(|stack| stack.peak_usage())
+    }
+
+    /// 检查当前栈是否发生了溢出
+    #[cfg(feature = "stack_protect")]
+    pub(crate) fn check_curr_stack_overflow(&self, task_id: u64) {
+        if let Some(stack) = &self.curr_stack {
+            stack.check_overflow(task_id);
+        }
     }
 
     /// Set current stack.
@@ -37,7 +162,30 @@ impl StackPool {
 
     /// Recycle an empty stack.
     /// SAFETY: the recycled stack must be empty and no longer used by a thread.
+    /// 启用`stack_protect`时，栈会被立即清零（启用`stack_stats`时为重新填充）。
     pub(crate) unsafe fn recycle_stack(&mut self, empty_stack: Arc<TaskStack>) {
-        self.free_stacks.push(empty_stack);
+        #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+        empty_stack.scrub();
+        self.push_free_stack(empty_stack);
+    }
+
+    /// 回收当前CPU正在使用的栈，用于run_next在换栈前回收当前栈的情况
+    /// 此时栈还在使用中，不能清零，因此推迟到栈被再次取出时进行。
+    /// SAFETY: 当前CPU在换栈之后才会再次从栈池中取出栈，此时该栈已不再被使用。
+    pub(crate) unsafe fn recycle_curr_stack(&mut self, stack: Arc<TaskStack>) {
+        #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+        stack.mark_dirty();
+        self.push_free_stack(stack);
+    }
+
+    fn push_free_stack(&mut self, stack: Arc<TaskStack>) {
+        #[cfg(feature = "stack_stats")]
+        {
+            CACHED_STACKS.fetch_add(1, Ordering::Relaxed);
+            let counters = &CPU_POOL_COUNTERS[self.cpu_id];
+            counters.cached.fetch_add(1, Ordering::Relaxed);
+            counters.recycled.fetch_add(1, Ordering::Relaxed);
+        }
+        self.free_stacks.entry(stack.size()).or_default().push(stack);
     }
 }
\ No newline at end of file
@@ -1,26 +1,161 @@
 // extern crate alloc;
 use core::{alloc::Layout, ptr::NonNull};
+#[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "stack_stats")]
+use core::sync::atomic::AtomicUsize;
 
-const TASK_STACK_SIZE: usize = 0x40000;
+/// 默认的任务栈大小
+pub(crate) const TASK_STACK_SIZE: usize = 0x40000;
+/// 任务栈大小的下限
+pub(crate) const MIN_TASK_STACK_SIZE: usize = 0x1000;
+/// 任务栈大小按该粒度向上取整，以减少栈池中大小类别的数量
+pub(crate) const TASK_STACK_SIZE_ALIGN: usize = 0x1000;
 
+/// 写在栈底的金丝雀值，被改写说明发生了栈溢出
+#[cfg(feature = "stack_protect")]
+const STACK_CANARY: usize = 0x5354_4143_4b43_4e59;
+#[cfg(feature = "stack_protect")]
+const STACK_CANARY_WORDS: usize = 4;
+
+#[cfg(all(feature = "stack_stats", feature = "stack_protect"))]
+const STACK_CANARY_SIZE: usize = STACK_CANARY_WORDS * core::mem::size_of::<usize>();
+#[cfg(all(feature = "stack_stats", not(feature = "stack_protect")))]
+const STACK_CANARY_SIZE: usize = 0;
+
+/// 填充栈空间的字节，未被改写的部分即为从未使用过的部分
+#[cfg(feature = "stack_stats")]
+const STACK_PAINT_BYTE: u8 = 0xa5;
+#[cfg(feature = "stack_stats")]
+const STACK_PAINT: usize = usize::from_ne_bytes([STACK_PAINT_BYTE; core::mem::size_of::<usize>()]);
+
+/// 已分配的栈的数量
+#[cfg(feature = "stack_stats")]
+pub(crate) static ALLOCATED_STACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// 栈底之下的保护页大小
+#[cfg(feature = "stack_guard_page")]
+const GUARD_PAGE_SIZE: usize = 0x1000;
+#[cfg(not(feature = "stack_guard_page"))]
+const GUARD_PAGE_SIZE: usize = 0;
+
+/// 由使用该模块的操作系统实现，将任务栈之下的保护页设为不可访问，从而使栈溢出触发缺页异常
+#[cfg(feature = "stack_guard_page")]
+#[crate_interface::def_interface]
+pub trait StackGuardPage {
+    /// 在分配任务栈后调用，`start`与`size`均按页对齐
+    fn protect_guard_page(start: usize, size: usize);
+    /// 在释放任务栈前调用，需恢复该区域的访问权限
+    fn unprotect_guard_page(start: usize, size: usize);
+}
+
+/// 内存布局（由低地址到高地址）：保护页（可选） | 金丝雀（可选） | 可用的栈空间
 pub(crate) struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
+    /// 栈被回收后、再次使用前需要清零（启用`stack_stats`时为重新填充）
+    #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+    needs_scrub: AtomicBool,
 }
 
 impl TaskStack {
-    pub fn alloc() -> Self {
-        let layout = Layout::from_size_align(TASK_STACK_SIZE, 16).unwrap();
-        Self {
+    pub fn alloc(size: usize) -> Self {
+        let align = if GUARD_PAGE_SIZE != 0 { GUARD_PAGE_SIZE } else { 16 };
+        let layout = Layout::from_size_align(size + GUARD_PAGE_SIZE, align).unwrap();
+        let stack = Self {
             ptr: NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap(),
             layout,
+            #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+            needs_scrub: AtomicBool::new(false),
+        };
+        #[cfg(feature = "stack_stats")]
+        {
+            ALLOCATED_STACKS.fetch_add(1, Ordering::Relaxed);
+            stack.paint();
         }
+        #[cfg(feature = "stack_guard_page")]
+        crate_interface::call_interface!(StackGuardPage::protect_guard_page(stack.ptr.as_ptr() as usize, GUARD_PAGE_SIZE));
+        #[cfg(feature = "stack_protect")]
+        stack.write_canary();
+        stack
+    }
+
+    /// 可用的栈空间大小（不包括保护页）
+    pub const fn size(&self) -> usize {
+        self.layout.size() - GUARD_PAGE_SIZE
+    }
+
+    /// 可用的栈空间的最低地址
+    #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+    pub fn bottom(&self) -> usize {
+        self.ptr.as_ptr() as usize + GUARD_PAGE_SIZE
     }
 
     pub const fn top(&self) -> usize {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
 
+    /// 检查栈底的金丝雀值，若被改写则以发生溢出的任务的id panic
+    #[cfg(feature = "stack_protect")]
+    pub(crate) fn check_overflow(&self, task_id: u64) {
+        let canary = self.bottom() as *const usize;
+        let intact = (0 .. STACK_CANARY_WORDS).all(|i| unsafe { canary.add(i).read_volatile() } == STACK_CANARY);
+        if !intact {
+            panic!("stack overflow detected in task {} (stack {:#x}..{:#x})", task_id, self.bottom(), self.top());
+        }
+    }
+
+    /// 标记栈需要在再次使用前清零
+    #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+    pub(crate) fn mark_dirty(&self) {
+        self.needs_scrub.store(true, Ordering::Release);
+    }
+
+    /// 若栈被标记为需要清零，则清零
+    /// 调用时栈不能正在被使用
+    #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+    pub(crate) fn scrub_if_dirty(&self) {
+        if self.needs_scrub.swap(false, Ordering::AcqRel) {
+            self.scrub();
+        }
+    }
+
+    /// 清零（启用`stack_stats`时为重新填充）并重新写入金丝雀值
+    /// 调用时栈不能正在被使用
+    #[cfg(any(feature = "stack_protect", feature = "stack_stats"))]
+    pub(crate) fn scrub(&self) {
+        #[cfg(feature = "stack_stats")]
+        self.paint();
+        #[cfg(not(feature = "stack_stats"))]
+        unsafe { core::ptr::write_bytes(self.bottom() as *mut u8, 0, self.size()); }
+        #[cfg(feature = "stack_protect")]
+        self.write_canary();
+    }
+
+    /// 栈的最大使用量（字节），即从栈顶到最低的被改写过的位置的距离
+    #[cfg(feature = "stack_stats")]
+    pub(crate) fn peak_usage(&self) -> usize {
+        let start = self.bottom() + STACK_CANARY_SIZE;
+        let words = (self.top() - start) / core::mem::size_of::<usize>();
+        let painted = (0 .. words)
+            .take_while(|&i| unsafe { (start as *const usize).add(i).read_volatile() } == STACK_PAINT)
+            .count();
+        self.top() - start - painted * core::mem::size_of::<usize>()
+    }
+
+    #[cfg(feature = "stack_stats")]
+    fn paint(&self) {
+        unsafe { core::ptr::write_bytes(self.bottom() as *mut u8, STACK_PAINT_BYTE, self.size()); }
+    }
+
+    #[cfg(feature = "stack_protect")]
+    fn write_canary(&self) {
+        let canary = self.bottom() as *mut usize;
+        for i in 0 .. STACK_CANARY_WORDS {
+            unsafe { canary.add(i).write_volatile(STACK_CANARY); }
+        }
+    }
+
     // #[cfg(feature = "monolithic")]
     // /// 获取内核栈第一个压入的trap上下文，防止出现内核trap嵌套
     // pub fn get_first_trap_frame(&self) -> *mut TrapFrame {
@@ -30,6 +165,10 @@ impl TaskStack {
 
 impl Drop for TaskStack {
     fn drop(&mut self) {
+        #[cfg(feature = "stack_stats")]
+        ALLOCATED_STACKS.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "stack_guard_page")]
+        crate_interface::call_interface!(StackGuardPage::unprotect_guard_page(self.ptr.as_ptr() as usize, GUARD_PAGE_SIZE));
         unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,99 @@
+use core::future::Future;
+
+use spinlock::SpinNoIrq;
+
+use crate::{api::yield_helper, task::switch_entry, BlockQueue};
+
+use super::MutexGuard;
+
+/// 与`Mutex`配合使用的条件变量
+pub struct Condvar {
+    wait_queue: SpinNoIrq<BlockQueue>,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self {
+            wait_queue: SpinNoIrq::new(BlockQueue::new()),
+        }
+    }
+
+    /// 释放互斥锁并阻塞，被唤醒后重新获取互斥锁（线程版本）
+    /// 与`std::sync::Condvar`相同，可能出现虚假唤醒，调用者应在循环中检查条件，或使用`wait_while`。
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        self.prepare_wait();
+        drop(guard);
+        switch_entry(true);
+        mutex.lock()
+    }
+
+    /// 释放互斥锁并阻塞，被唤醒后重新获取互斥锁（协程版本）
+    /// 守卫不是`Send`的，不能被保存在Future中，因此在返回Future之前就释放互斥锁
+    pub fn wait_async<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> impl Future<Output = MutexGuard<'a, T>> + 'a {
+        self.reacquire_async(guard, true)
+    }
+
+    /// 在`condition`返回true时持续等待（线程版本）
+    pub fn wait_while<'a, T: ?Sized, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F) -> MutexGuard<'a, T>
+    where F: FnMut(&mut T) -> bool {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// 在`condition`返回true时持续等待（协程版本）
+    /// 与`wait_async`相同，在返回Future之前就释放互斥锁，因此条件不成立时也会重新获取一次互斥锁
+    pub fn wait_while_async<'a, 'b, T: ?Sized, F>(&'b self, mut guard: MutexGuard<'a, T>, mut condition: F) -> impl Future<Output = MutexGuard<'a, T>> + 'b
+    where F: FnMut(&mut T) -> bool + 'b, 'a: 'b {
+        let wait = condition(&mut *guard);
+        let mut reacquire = self.reacquire_async(guard, wait);
+        async move {
+            loop {
+                let mut guard = reacquire.await;
+                if !condition(&mut *guard) {
+                    return guard;
+                }
+                reacquire = self.reacquire_async(guard, true);
+            }
+        }
+    }
+
+    /// 唤醒一个等待的任务
+    pub fn notify_one(&self) {
+        self.wait_queue.lock().wake_one_to_local();
+    }
+
+    /// 唤醒所有等待的任务
+    pub fn notify_all(&self) {
+        self.wait_queue.lock().wake_all_to_local();
+    }
+
+    /// 释放互斥锁，返回重新获取互斥锁的Future。`wait`为true时，在释放之前加入等待队列，并在重新获取之前等待通知
+    fn reacquire_async<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>, wait: bool) -> impl Future<Output = MutexGuard<'a, T>> + 'a {
+        let mutex = guard.mutex;
+        if wait {
+            self.prepare_wait();
+        }
+        drop(guard);
+        async move {
+            if wait {
+                yield_helper().await;
+            }
+            mutex.lock_async().await
+        }
+    }
+
+    /// 将当前任务加入等待队列
+    /// 需要在释放互斥锁之前完成，从而不会错过释放互斥锁与切换之间发生的通知
+    fn prepare_wait(&self) {
+        BlockQueue::prepare_block_current(&self.wait_queue, |wait_queue| wait_queue.lock(), |wait_queue| Some(&mut **wait_queue));
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
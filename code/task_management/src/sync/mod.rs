@@ -0,0 +1,38 @@
+//! 可睡眠的同步原语
+//!
+//! 这些同步原语及`mpsc`、`oneshot`通道基于阻塞队列实现，获取失败时会阻塞当前任务，而不是自旋等待。
+//! 每个原语都同时提供线程接口（如`lock`）和协程接口（如`lock_async`），因此同一个实例可以在线程和协程之间共享。
+
+use alloc::sync::Arc;
+
+use crate::{current_ptr, Task};
+
+mod condvar;
+pub mod mpsc;
+mod mutex;
+pub mod oneshot;
+mod rwlock;
+mod semaphore;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+
+/// 记录当前任务持有一个可睡眠的锁，析构时撤销记录
+/// 锁守卫可能被移动到其它任务中析构，因此保存获取锁的任务
+pub(crate) struct HeldLock(Arc<Task>);
+
+impl HeldLock {
+    pub(crate) fn new() -> Self {
+        let current = current_ptr();
+        current.lock_acquired();
+        Self(current)
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        self.0.lock_released();
+    }
+}
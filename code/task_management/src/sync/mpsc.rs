@@ -0,0 +1,248 @@
+//! 多生产者、单消费者通道
+//!
+//! `send`与`recv`在需要等待时都会阻塞线程或挂起协程，因此线程和协程可以通过同一个通道通信。
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::fmt;
+use spinlock::SpinNoIrq;
+
+use crate::{api::yield_helper, task::switch_entry, BlockQueue, WaitScope};
+
+/// 创建无界通道，发送操作永远不会阻塞
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    new_channel(None)
+}
+
+/// 创建有界通道，缓冲区满时发送操作会阻塞
+/// `bound`必须大于0
+pub fn bounded<T>(bound: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(bound > 0, "the bound of a channel must be greater than 0");
+    new_channel(Some(bound))
+}
+
+fn new_channel<T>(bound: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(SpinNoIrq::new(Channel {
+        buffer: VecDeque::new(),
+        bound,
+        senders: 1,
+        receiver_alive: true,
+        send_queue: BlockQueue::new(),
+        recv_queue: BlockQueue::new(),
+    }));
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+struct Channel<T> {
+    buffer: VecDeque<T>,
+    /// 为None代表无界通道
+    bound: Option<usize>,
+    /// 存活的发送端数量
+    senders: usize,
+    receiver_alive: bool,
+    /// 等待缓冲区有空位的发送者
+    send_queue: BlockQueue,
+    /// 等待缓冲区有数据的接收者
+    recv_queue: BlockQueue,
+}
+
+impl<T> Channel<T> {
+    fn is_full(&self) -> bool {
+        self.bound.is_some_and(|bound| self.buffer.len() >= bound)
+    }
+}
+
+/// 通道的发送端，可以复制
+pub struct Sender<T> {
+    shared: Arc<SpinNoIrq<Channel<T>>>,
+}
+
+/// 通道的接收端
+pub struct Receiver<T> {
+    shared: Arc<SpinNoIrq<Channel<T>>>,
+}
+
+/// 接收端已关闭，返回未发送的数据
+pub struct SendError<T>(pub T);
+
+/// 所有发送端已关闭且缓冲区为空
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+pub enum TrySendError<T> {
+    /// 缓冲区已满
+    Full(T),
+    /// 接收端已关闭
+    Disconnected(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// 缓冲区为空
+    Empty,
+    /// 所有发送端已关闭且缓冲区为空
+    Disconnected,
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError { .. }")
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Disconnected(_) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// 发送数据（线程版本）
+    pub fn send(&self, mut value: T) -> Result<(), SendError<T>> {
+        let _scope = WaitScope::new();
+        loop {
+            match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(v)) => return Err(SendError(v)),
+                Err(TrySendError::Full(v)) => value = v,
+            }
+            if self.prepare_block() {
+                switch_entry(true);
+            }
+        }
+    }
+
+    /// 发送数据（协程版本）
+    pub async fn send_async(&self, mut value: T) -> Result<(), SendError<T>> {
+        let _scope = WaitScope::new();
+        loop {
+            match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(v)) => return Err(SendError(v)),
+                Err(TrySendError::Full(v)) => value = v,
+            }
+            if self.prepare_block() {
+                yield_helper().await;
+            }
+        }
+    }
+
+    /// 尝试发送数据，缓冲区满时不阻塞
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut channel = self.shared.lock();
+        if !channel.receiver_alive {
+            Err(TrySendError::Disconnected(value))
+        }
+        else if channel.is_full() {
+            Err(TrySendError::Full(value))
+        }
+        else {
+            channel.buffer.push_back(value);
+            channel.recv_queue.wake_one_to_local();
+            Ok(())
+        }
+    }
+
+    /// 若缓冲区仍然已满，则将当前任务加入发送者的等待队列
+    /// 返回值代表是否进行了阻塞，若为false，调用者应重新尝试发送
+    fn prepare_block(&self) -> bool {
+        BlockQueue::prepare_block_current(&*self.shared, |shared| shared.lock(), |channel| {
+            if channel.receiver_alive && channel.is_full() {
+                Some(&mut channel.send_queue)
+            }
+            else {
+                None
+            }
+        })
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut channel = self.shared.lock();
+        channel.senders -= 1;
+        if channel.senders == 0 {
+            channel.recv_queue.wake_all_to_local();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// 接收数据（线程版本）
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let _scope = WaitScope::new();
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            if self.prepare_block() {
+                switch_entry(true);
+            }
+        }
+    }
+
+    /// 接收数据（协程版本）
+    pub async fn recv_async(&self) -> Result<T, RecvError> {
+        let _scope = WaitScope::new();
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            if self.prepare_block() {
+                yield_helper().await;
+            }
+        }
+    }
+
+    /// 尝试接收数据，缓冲区为空时不阻塞
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut channel = self.shared.lock();
+        match channel.buffer.pop_front() {
+            Some(value) => {
+                channel.send_queue.wake_one_to_local();
+                Ok(value)
+            }
+            None if channel.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// 若缓冲区仍然为空且存在发送端，则将当前任务加入接收者的等待队列
+    /// 返回值代表是否进行了阻塞，若为false，调用者应重新尝试接收
+    fn prepare_block(&self) -> bool {
+        BlockQueue::prepare_block_current(&*self.shared, |shared| shared.lock(), |channel| {
+            if channel.senders > 0 && channel.buffer.is_empty() {
+                Some(&mut channel.recv_queue)
+            }
+            else {
+                None
+            }
+        })
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // 缓冲区中剩余的数据在释放锁之后再析构
+        let buffer = {
+            let mut channel = self.shared.lock();
+            channel.receiver_alive = false;
+            channel.send_queue.wake_all_to_local();
+            core::mem::take(&mut channel.buffer)
+        };
+        drop(buffer);
+    }
+}
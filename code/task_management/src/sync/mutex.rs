@@ -0,0 +1,161 @@
+use core::{cell::UnsafeCell, fmt, marker::PhantomData, ops::{Deref, DerefMut}};
+use spinlock::SpinNoIrq;
+
+use crate::{api::yield_helper, current_id, task::switch_entry, BlockQueue, WaitCancel};
+
+use super::HeldLock;
+
+/// 可睡眠的互斥锁
+/// 锁被占用时，`lock`会阻塞当前线程，`lock_async`会挂起当前协程。
+pub struct Mutex<T: ?Sized> {
+    inner: SpinNoIrq<MutexInner>,
+    data: UnsafeCell<T>,
+}
+
+struct MutexInner {
+    /// 持有锁的任务的id，为0代表锁未被持有
+    owner_id: u64,
+    wait_queue: BlockQueue,
+}
+
+/// 互斥锁的守卫，离开作用域时释放锁
+/// 锁由获取它的任务持有，因此守卫不能被发送到其它任务（协程中也不能跨越`await`持有守卫）。
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    pub(crate) mutex: &'a Mutex<T>,
+    _held: HeldLock,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for MutexGuard<'a, T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            inner: SpinNoIrq::new(MutexInner {
+                owner_id: 0,
+                wait_queue: BlockQueue::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// 获取锁（线程版本）
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let current_id = current_id();
+        while self.prepare_lock(current_id) {
+            switch_entry(true);
+        }
+        MutexGuard::new(self)
+    }
+
+    /// 获取锁（协程版本）
+    pub async fn lock_async(&self) -> MutexGuard<'_, T> {
+        let current_id = current_id();
+        while self.prepare_lock(current_id) {
+            yield_helper().await;
+        }
+        MutexGuard::new(self)
+    }
+
+    /// 尝试获取锁，锁被占用时不阻塞，直接返回None
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let current_id = current_id();
+        let mut inner = self.inner.lock();
+        if inner.owner_id == 0 {
+            inner.owner_id = current_id;
+            Some(MutexGuard::new(self))
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.lock().owner_id != 0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// 尝试获取锁，若锁被占用则将当前任务加入等待队列
+    /// 返回值代表是否进行了阻塞
+    fn prepare_lock(&self, current_id: u64) -> bool {
+        BlockQueue::prepare_block_current_with_cancel(&self.inner, |inner| inner.lock(), |inner| {
+            if inner.owner_id == 0 {
+                inner.owner_id = current_id;
+                None
+            }
+            else {
+                assert!(inner.owner_id != current_id, "task {} tried to lock a mutex it already holds!", current_id);
+                Some(&mut inner.wait_queue)
+            }
+        })
+    }
+
+    /// 释放锁，并唤醒一个等待的任务。被唤醒的任务会重新竞争锁。
+    pub(crate) fn unlock(&self) {
+        let mut inner = self.inner.lock();
+        inner.owner_id = 0;
+        inner.wait_queue.wake_one_to_local();
+    }
+}
+
+/// 等待的任务被终止时，它可能已被`unlock`唤醒、但还未重新竞争锁，因此锁空闲时唤醒另一个等待的任务
+impl WaitCancel for SpinNoIrq<MutexInner> {
+    fn cancel_wait(&self, _queue: &BlockQueue) {
+        let inner = self.lock();
+        if inner.owner_id == 0 {
+            inner.wait_queue.wake_one_to_local();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> Self {
+        Self { mutex, _held: HeldLock::new(), _not_send: PhantomData }
+    }
+}
+
+impl<T: ?Sized + Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("Mutex").field("data", &"<locked>").finish(),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
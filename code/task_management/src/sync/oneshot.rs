@@ -0,0 +1,123 @@
+//! 只能发送一次数据的通道
+
+use alloc::sync::Arc;
+use spinlock::SpinNoIrq;
+
+use crate::{api::yield_helper, task::switch_entry, BlockQueue, WaitScope};
+
+pub use super::mpsc::{RecvError, TryRecvError};
+
+/// 创建一次性通道
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(SpinNoIrq::new(Oneshot {
+        value: None,
+        sender_alive: true,
+        receiver_alive: true,
+        recv_queue: BlockQueue::new(),
+    }));
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+struct Oneshot<T> {
+    value: Option<T>,
+    sender_alive: bool,
+    receiver_alive: bool,
+    recv_queue: BlockQueue,
+}
+
+/// 一次性通道的发送端，发送操作不会阻塞
+pub struct Sender<T> {
+    shared: Arc<SpinNoIrq<Oneshot<T>>>,
+}
+
+/// 一次性通道的接收端
+pub struct Receiver<T> {
+    shared: Arc<SpinNoIrq<Oneshot<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// 发送数据并关闭发送端
+    /// 若接收端已关闭，则返回未发送的数据
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut oneshot = self.shared.lock();
+        if !oneshot.receiver_alive {
+            return Err(value);
+        }
+        oneshot.value = Some(value);
+        Ok(())
+        // 在drop中关闭发送端并唤醒接收者
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut oneshot = self.shared.lock();
+        oneshot.sender_alive = false;
+        oneshot.recv_queue.wake_all_to_local();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// 接收数据（线程版本）
+    pub fn recv(self) -> Result<T, RecvError> {
+        let _scope = WaitScope::new();
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            if self.prepare_block() {
+                switch_entry(true);
+            }
+        }
+    }
+
+    /// 接收数据（协程版本）
+    pub async fn recv_async(self) -> Result<T, RecvError> {
+        let _scope = WaitScope::new();
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            if self.prepare_block() {
+                yield_helper().await;
+            }
+        }
+    }
+
+    /// 尝试接收数据，不阻塞
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut oneshot = self.shared.lock();
+        match oneshot.value.take() {
+            Some(value) => Ok(value),
+            None if !oneshot.sender_alive => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// 返回值代表是否进行了阻塞，若为false，调用者应重新尝试接收
+    fn prepare_block(&self) -> bool {
+        BlockQueue::prepare_block_current(&*self.shared, |shared| shared.lock(), |oneshot| {
+            if oneshot.sender_alive && oneshot.value.is_none() {
+                Some(&mut oneshot.recv_queue)
+            }
+            else {
+                None
+            }
+        })
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let value = {
+            let mut oneshot = self.shared.lock();
+            oneshot.receiver_alive = false;
+            oneshot.value.take()
+        };
+        drop(value);
+    }
+}
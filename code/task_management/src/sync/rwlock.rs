@@ -0,0 +1,237 @@
+use core::{cell::UnsafeCell, marker::PhantomData, ops::{Deref, DerefMut}};
+use spinlock::SpinNoIrq;
+
+use crate::{api::yield_helper, task::switch_entry, BlockQueue, WaitCancel};
+
+use super::HeldLock;
+
+/// 可睡眠的读写锁
+/// 写者优先：有写者在等待时，新的读者会被阻塞，以免写者饥饿。
+pub struct RwLock<T: ?Sized> {
+    inner: SpinNoIrq<RwLockInner>,
+    data: UnsafeCell<T>,
+}
+
+struct RwLockInner {
+    readers: usize,
+    writer: bool,
+    waiting_writers: usize,
+    read_queue: BlockQueue,
+    write_queue: BlockQueue,
+}
+
+/// 读锁的守卫，离开作用域时释放读锁
+/// 与`MutexGuard`相同，守卫不能被发送到其它任务。
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    _held: HeldLock,
+    _not_send: PhantomData<*const ()>,
+}
+
+/// 写锁的守卫，离开作用域时释放写锁
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    _held: HeldLock,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for RwLockReadGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for RwLockWriteGuard<'a, T> {}
+
+impl<T> RwLock<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            inner: SpinNoIrq::new(RwLockInner {
+                readers: 0,
+                writer: false,
+                waiting_writers: 0,
+                read_queue: BlockQueue::new(),
+                write_queue: BlockQueue::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// 获取读锁（线程版本）
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        while self.prepare_read() {
+            switch_entry(true);
+        }
+        RwLockReadGuard { lock: self, _held: HeldLock::new(), _not_send: PhantomData }
+    }
+
+    /// 获取读锁（协程版本）
+    pub async fn read_async(&self) -> RwLockReadGuard<'_, T> {
+        while self.prepare_read() {
+            yield_helper().await;
+        }
+        RwLockReadGuard { lock: self, _held: HeldLock::new(), _not_send: PhantomData }
+    }
+
+    /// 获取写锁（线程版本）
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let mut waited = false;
+        while self.prepare_write(waited) {
+            waited = true;
+            switch_entry(true);
+        }
+        RwLockWriteGuard { lock: self, _held: HeldLock::new(), _not_send: PhantomData }
+    }
+
+    /// 获取写锁（协程版本）
+    pub async fn write_async(&self) -> RwLockWriteGuard<'_, T> {
+        let mut waited = false;
+        while self.prepare_write(waited) {
+            waited = true;
+            yield_helper().await;
+        }
+        RwLockWriteGuard { lock: self, _held: HeldLock::new(), _not_send: PhantomData }
+    }
+
+    /// 尝试获取读锁，不阻塞
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut inner = self.inner.lock();
+        if inner.writer || inner.waiting_writers > 0 {
+            None
+        }
+        else {
+            inner.readers += 1;
+            Some(RwLockReadGuard { lock: self, _held: HeldLock::new(), _not_send: PhantomData })
+        }
+    }
+
+    /// 尝试获取写锁，不阻塞
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let mut inner = self.inner.lock();
+        if inner.writer || inner.readers > 0 {
+            None
+        }
+        else {
+            inner.writer = true;
+            Some(RwLockWriteGuard { lock: self, _held: HeldLock::new(), _not_send: PhantomData })
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// 返回值代表是否进行了阻塞
+    fn prepare_read(&self) -> bool {
+        BlockQueue::prepare_block_current_with_cancel(&self.inner, |inner| inner.lock(), |inner| {
+            if inner.writer || inner.waiting_writers > 0 {
+                Some(&mut inner.read_queue)
+            }
+            else {
+                inner.readers += 1;
+                None
+            }
+        })
+    }
+
+    /// `waited`代表当前任务是否已经在写者队列中等待过，若是，则先将其从等待的写者数量中减去
+    /// 返回值代表是否进行了阻塞
+    fn prepare_write(&self, waited: bool) -> bool {
+        BlockQueue::prepare_block_current_with_cancel(&self.inner, |inner| inner.lock(), |inner| {
+            if waited {
+                inner.waiting_writers -= 1;
+            }
+            if inner.writer || inner.readers > 0 {
+                inner.waiting_writers += 1;
+                Some(&mut inner.write_queue)
+            }
+            else {
+                inner.writer = true;
+                None
+            }
+        })
+    }
+
+    fn read_unlock(&self) {
+        let mut inner = self.inner.lock();
+        inner.readers -= 1;
+        if inner.readers == 0 && inner.waiting_writers > 0 {
+            inner.write_queue.wake_one_to_local();
+        }
+    }
+
+    fn write_unlock(&self) {
+        let mut inner = self.inner.lock();
+        inner.writer = false;
+        inner.wake_waiters();
+    }
+}
+
+impl RwLockInner {
+    /// 锁空闲时，优先唤醒等待的写者，没有写者在等待时唤醒所有读者
+    fn wake_waiters(&self) {
+        if self.writer || self.readers > 0 {
+            return;
+        }
+        if self.waiting_writers > 0 {
+            self.write_queue.wake_one_to_local();
+        }
+        else {
+            self.read_queue.wake_all_to_local();
+        }
+    }
+}
+
+/// 等待的写者被终止时，将其从等待的写者数量中减去；被终止的任务可能已被唤醒，因此重新唤醒等待者
+impl WaitCancel for SpinNoIrq<RwLockInner> {
+    fn cancel_wait(&self, queue: &BlockQueue) {
+        let mut inner = self.lock();
+        if queue.same_queue(&inner.write_queue) {
+            inner.waiting_writers -= 1;
+        }
+        inner.wake_waiters();
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
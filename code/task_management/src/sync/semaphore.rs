@@ -0,0 +1,94 @@
+use spinlock::SpinNoIrq;
+
+use crate::{api::yield_helper, task::switch_entry, BlockQueue, WaitCancel};
+
+/// 可睡眠的计数信号量
+/// 没有可用的许可时，`acquire`会阻塞当前线程，`acquire_async`会挂起当前协程。
+pub struct Semaphore {
+    inner: SpinNoIrq<SemaphoreInner>,
+}
+
+struct SemaphoreInner {
+    count: usize,
+    wait_queue: BlockQueue,
+}
+
+impl Semaphore {
+    pub fn new(count: usize) -> Self {
+        Self {
+            inner: SpinNoIrq::new(SemaphoreInner {
+                count,
+                wait_queue: BlockQueue::new(),
+            }),
+        }
+    }
+
+    /// 获取一个许可（线程版本）
+    pub fn acquire(&self) {
+        while self.prepare_acquire() {
+            switch_entry(true);
+        }
+    }
+
+    /// 获取一个许可（协程版本）
+    pub async fn acquire_async(&self) {
+        while self.prepare_acquire() {
+            yield_helper().await;
+        }
+    }
+
+    /// 尝试获取一个许可，没有可用的许可时不阻塞，直接返回false
+    pub fn try_acquire(&self) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.count > 0 {
+            inner.count -= 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// 释放一个许可，并唤醒一个等待的任务
+    pub fn release(&self) {
+        let mut inner = self.inner.lock();
+        inner.count += 1;
+        inner.wait_queue.wake_one_to_local();
+    }
+
+    /// 释放一个许可，并唤醒一个等待的任务，放入全局调度器
+    pub fn release_to_global(&self) {
+        let mut inner = self.inner.lock();
+        inner.count += 1;
+        inner.wait_queue.wake_one_to_global();
+    }
+
+    /// 当前可用的许可数量
+    pub fn available_permits(&self) -> usize {
+        self.inner.lock().count
+    }
+
+    /// 尝试获取一个许可，若没有可用的许可则将当前任务加入等待队列
+    /// 返回值代表是否进行了阻塞
+    fn prepare_acquire(&self) -> bool {
+        BlockQueue::prepare_block_current_with_cancel(&self.inner, |inner| inner.lock(), |inner| {
+            if inner.count > 0 {
+                inner.count -= 1;
+                None
+            }
+            else {
+                Some(&mut inner.wait_queue)
+            }
+        })
+    }
+}
+
+/// 等待的任务被终止时，它可能已被`release`唤醒、但还未取得许可，因此还有可用的许可时唤醒另一个等待的任务
+impl WaitCancel for SpinNoIrq<SemaphoreInner> {
+    fn cancel_wait(&self, _queue: &BlockQueue) {
+        let inner = self.lock();
+        if inner.count > 0 {
+            inner.wait_queue.wake_one_to_local();
+        }
+    }
+}
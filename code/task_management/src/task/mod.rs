@@ -1,5 +1,5 @@
-use core::{future::{poll_fn, Future, Pending}, mem::ManuallyDrop, pin::Pin, ptr::NonNull, sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering}, task::Poll};
-use alloc::{boxed::Box, sync::Arc};
+use core::{any::Any, alloc::Layout, future::{pending, poll_fn, Future, Pending}, mem::ManuallyDrop, pin::Pin, ptr::NonNull, sync::atomic::{AtomicBool, AtomicI32, AtomicIsize, AtomicU64, AtomicUsize, Ordering}, task::Poll, time::Duration};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc};
 use axlog::debug;
 use spinlock::{SpinNoIrq, SpinNoIrqGuard, SpinNoIrqOnly, SpinNoIrqOnlyGuard};
 use crossbeam::atomic::AtomicCell;
@@ -11,8 +11,15 @@ mod waker;
 
 pub use reg_context::TaskContext;
 pub(crate) use switch::{preempt_switch_entry, switch_entry};
+#[cfg(feature = "switch_hook")]
+pub use switch::TaskSwitchIf;
+pub(crate) use waker::waker_from_task;
 
-use crate::{exit_current, exit_current_async, processor::Processor, stack::TaskStack};
+#[cfg(feature = "hpm")]
+use crate::hpm::HpmAccount;
+#[cfg(feature = "trace")]
+use crate::trace::{self, TraceEvent};
+use crate::{exit_current, exit_current_async, processor::Processor, registry, timer, stack::{TaskStack, TASK_STACK_SIZE}, BlockQueue};
 
 pub type Task = AxTask<TaskInner>;
 
@@ -30,6 +37,12 @@ pub struct TaskInner {
     /// original任务代表运行任务前、CPU已有的执行流。在该执行流上调用init_processor系列函数。
     /// 将原本的执行流作为任务保存，是为了之后可以切回该任务，从而使CPU回到该原有执行流。
     is_original: bool,
+    /// 任务名，仅用于调试
+    name: Option<String>,
+    /// 任务运行时使用的栈的大小
+    stack_size: usize,
+    /// 创建时指定的任务类型
+    kind: TaskKind,
 
     // -----可变属性-----
 
@@ -56,10 +69,78 @@ pub struct TaskInner {
     /// 返回值
     exit_code: AtomicI32,
 
-    // 目前不考虑
-    // /// CPU亲和性
-    // /// 用位图存储
-    // cpu_set: AtomicU64,
+    /// 调度器中设置的优先级的副本，仅用于查询
+    priority: AtomicIsize,
+    /// 是否设置过优先级，为false代表使用调度器的默认值。任何`isize`值都可能是有效的优先级，因此不使用特殊值表示
+    priority_set: AtomicBool,
+
+    /// 最近一次运行该任务的CPU的id，为`usize::MAX`代表还未运行过
+    last_cpu: AtomicUsize,
+
+    /// 任务阻塞时所在的阻塞队列的地址，为0代表未在阻塞队列中。仅用于调试
+    waiting_on: AtomicUsize,
+
+    /// 任务阻塞时所在的阻塞队列，超时事件通过它将任务移出队列
+    /// 任务离开阻塞操作时才清除，因此记录存在期间，阻塞队列仍被阻塞中的任务借用，不会被释放或移动
+    wait_site: SpinNoIrqOnly<Option<WaitSite>>,
+
+    /// 等待该任务退出的任务（join）
+    join_queue: SpinNoIrq<BlockQueue>,
+
+    /// 带超时的阻塞的状态
+    /// 低2位为`TIMED_WAIT_*`状态，其余位为每次阻塞递增的序号，用于区分已经失效的超时事件
+    timed_wait: AtomicU64,
+
+    /// 任务正在运行时被`kill`，等待在下一次切换时退出
+    pending_kill: AtomicBool,
+
+    /// 任务获取的、还未释放的可睡眠锁（`Mutex`、`RwLock`）的数量
+    held_locks: AtomicUsize,
+
+    /// 当前任务让出或被抢占后，是否放入全局调度器（而不是当前CPU的调度器）
+    /// 在切换过程中、任务的上下文保存完成后读取并清除
+    requeue_to_global: AtomicBool,
+
+    /// 由使用该模块的系统解释的数据，在切换钩子中传入
+    user_data: AtomicUsize,
+
+    /// 任务局部变量，以`LocalKey`的地址为键
+    locals: SpinNoIrqOnly<BTreeMap<usize, Box<dyn Any + Send>>>,
+
+    /// 使用该模块的系统附加在任务上的数据（如进程、文件表），在创建任务时设置，之后不可修改
+    ext: Option<Box<dyn Any + Send + Sync>>,
+
+    /// 任务是否因被抢占而切换，在抢占入口设置，在切换过程中读取并清除
+    preempted: AtomicBool,
+
+    /// 协程在本次poll中等待Waker的唤醒（如`Sleep`）：poll返回Pending后，若期间未被唤醒，则阻塞任务而不是将其放回调度器
+    park_requested: AtomicBool,
+
+    /// 任务在运行期间被唤醒，用于使`park_requested`的任务不会错过poll期间发生的唤醒
+    notified: AtomicBool,
+
+    /// 任务最近一次开始在CPU上运行时time寄存器的值，为0代表未在运行
+    on_cpu_since: AtomicU64,
+
+    /// 累计运行时间（time寄存器的tick数），不含正在进行的一次运行
+    runtime_ticks: AtomicU64,
+
+    /// 主动让出CPU（让出、阻塞、退出）的次数
+    voluntary_switches: AtomicU64,
+
+    /// 被抢占的次数
+    preemptions: AtomicU64,
+
+    /// 从阻塞中被唤醒的次数
+    wakeups: AtomicU64,
+
+    /// 硬件性能计数器的统计
+    #[cfg(feature = "hpm")]
+    hpm: HpmAccount,
+
+    /// CPU亲和性
+    /// 用位图存储，第i位为1代表任务可以在id为i的CPU上运行
+    cpu_set: AtomicU64,
 
     /// 禁止抢占计数
     #[cfg(feature = "preempt")]
@@ -74,6 +155,10 @@ pub struct TaskInner {
     /// 我打算在我的模块中同时支持线程和协程。线程总是使用ctx_ref，而不使用future。
     ctx_ref: AtomicCell<NonNull<TaskContext>>,
 
+    /// 任务的栈的最大使用量（字节），在回收栈或查询时更新
+    #[cfg(feature = "stack_stats")]
+    stack_peak: AtomicUsize,
+
     /// 保存寄存器上下文时，任务持有的栈
     /// 任务正在运行或以Future形式保存上下文时，该字段为None
     owned_stack: AtomicCell<Option<Arc<TaskStack>>>,
@@ -83,15 +168,77 @@ pub struct TaskInner {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[allow(missing_docs)]
-pub(crate) enum TaskState {
-    /// 该状态可能正在执行，也可能就绪
-    Runable = 1,
+pub enum TaskState {
+    /// 正在CPU上执行（是某个CPU的当前任务）
+    Running = 1,
+    /// 就绪，位于某个调度器中，或即将被放入调度器
+    Ready = 2,
     /// 设置Blocking状态 -> 加入阻塞队列 -> 保存上下文 -> 设置Blocked状态
-    Blocking = 2,
+    Blocking = 3,
     /// 设置Blocking状态 -> 加入阻塞队列 -> 保存上下文 -> 设置Blocked状态
-    Blocked = 3,
+    Blocked = 4,
     /// 保存返回值 -> 设置Exited状态 -> 停止执行
-    Exited = 4,
+    Exited = 5,
+}
+
+/// 任务的运行时间与切换次数统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TaskStats {
+    /// 累计运行时间，包含正在进行的一次运行
+    pub runtime: Duration,
+    /// 主动让出CPU（让出、阻塞、退出）的次数
+    pub voluntary_switches: u64,
+    /// 被抢占的次数
+    pub preemptions: u64,
+    /// 从阻塞中被唤醒的次数
+    pub wakeups: u64,
+}
+
+/// 任务的类型
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TaskKind {
+    /// 以同步函数创建，切换时保存寄存器上下文
+    Thread,
+    /// 以Future创建，切换时保存在Future中
+    Coroutine,
+}
+
+const TIMED_WAIT_NONE: u64 = 0;
+const TIMED_WAIT_WAITING: u64 = 1;
+const TIMED_WAIT_TIMED_OUT: u64 = 2;
+const TIMED_WAIT_STATE_MASK: u64 = 0b11;
+
+/// 任务阻塞时所在的阻塞队列
+/// 记录持有队列的共享引用，因此即使队列在任务阻塞期间被替换或释放，超时与终止操作访问的队列仍然有效。
+pub(crate) struct WaitSite {
+    pub(crate) queue: BlockQueue,
+    pub(crate) owner: Option<WaitOwner>,
+}
+
+/// 阻塞队列所属的同步原语，任务被终止时由`cancel`修正其状态
+pub(crate) struct WaitOwner {
+    pub(crate) owner: *const (),
+    pub(crate) cancel: unsafe fn(*const (), &BlockQueue),
+}
+
+// 同步原语在其中的任务阻塞期间被任务借用，因此不会被释放
+unsafe impl Send for WaitSite {}
+
+/// 创建任务时可以指定的属性
+pub(crate) struct TaskOptions {
+    pub(crate) name: Option<String>,
+    pub(crate) stack_size: usize,
+    pub(crate) ext: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl Default for TaskOptions {
+    fn default() -> Self {
+        Self {
+            name: None,
+            stack_size: TASK_STACK_SIZE,
+            ext: None,
+        }
+    }
 }
 
 /// A unique identifier for a thread.
@@ -135,7 +282,7 @@ unsafe impl Sync for TaskInner {}
 /// 访问各个成员的方法
 impl TaskInner {
     #[inline]
-    pub(crate) fn id(&self) -> u64 {
+    pub fn id(&self) -> u64 {
         self.id.as_u64()
     }
 
@@ -154,6 +301,117 @@ impl TaskInner {
         self.is_original
     }
 
+    #[inline]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    #[inline]
+    pub fn kind(&self) -> TaskKind {
+        self.kind
+    }
+
+    /// 由使用该模块的系统解释的数据（如页表、FPU状态的地址），本模块只负责保存，并在切换钩子中传入
+    #[inline]
+    pub fn user_data(&self) -> usize {
+        self.user_data.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_user_data(&self, user_data: usize) {
+        self.user_data.store(user_data, Ordering::Release)
+    }
+
+    /// 键为`key`的任务局部变量的地址，未初始化时返回None
+    pub(crate) fn task_local(&self, key: usize) -> Option<*const ()> {
+        self.locals.lock().get(&key).map(|value| &**value as *const dyn Any as *const ())
+    }
+
+    /// 插入任务局部变量并返回其地址。若已经存在（在初始化过程中被插入），则保留原有的值
+    pub(crate) fn insert_task_local(&self, key: usize, value: Box<dyn Any + Send>) -> *const () {
+        let mut locals = self.locals.lock();
+        let value = locals.entry(key).or_insert(value);
+        &**value as *const dyn Any as *const ()
+    }
+
+    /// 创建任务时附加的数据，未附加数据或类型不是`T`时返回None
+    #[inline]
+    pub fn ext<T: Any>(&self) -> Option<&T> {
+        self.ext.as_ref()?.downcast_ref::<T>()
+    }
+
+    /// 任务的优先级，为None代表创建后未设置过优先级，使用调度器的默认值
+    #[inline]
+    pub fn priority(&self) -> Option<isize> {
+        self.priority_set.load(Ordering::Acquire).then(|| self.priority.load(Ordering::Acquire))
+    }
+
+    #[inline]
+    pub(crate) fn set_priority_mirror(&self, priority: isize) {
+        self.priority.store(priority, Ordering::Release);
+        self.priority_set.store(true, Ordering::Release);
+    }
+
+    /// 最近一次运行该任务的CPU的id，为None代表还未运行过
+    #[inline]
+    pub fn last_cpu(&self) -> Option<usize> {
+        match self.last_cpu.load(Ordering::Acquire) {
+            usize::MAX => None,
+            cpu_id => Some(cpu_id),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn set_last_cpu(&self, cpu_id: usize) {
+        self.last_cpu.store(cpu_id, Ordering::Release)
+    }
+
+    /// 任务阻塞时所在的阻塞队列的地址，未在阻塞队列中时为None
+    /// 阻塞队列可能随其所在的数据结构移动，因此该地址仅用于调试时区分不同的队列
+    #[inline]
+    pub fn waiting_on(&self) -> Option<usize> {
+        match self.waiting_on.load(Ordering::Acquire) {
+            0 => None,
+            addr => Some(addr),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn set_waiting_on(&self, addr: usize) {
+        self.waiting_on.store(addr, Ordering::Release)
+    }
+
+    /// 记录任务阻塞时所在的阻塞队列
+    pub(crate) fn set_wait_site(&self, queue: &BlockQueue, owner: Option<WaitOwner>) {
+        *self.wait_site.lock() = Some(WaitSite { queue: queue.share(), owner });
+        self.set_waiting_on(queue.addr());
+    }
+
+    /// 任务离开阻塞操作时调用，清除阻塞队列的记录
+    /// 记录可能持有队列的最后一个引用，因此在释放锁之后再将其丢弃
+    #[inline]
+    pub(crate) fn clear_wait_site(&self) {
+        let site = self.wait_site.lock().take();
+        drop(site);
+    }
+
+    /// 任务被终止时调用，取出阻塞队列的记录
+    #[inline]
+    pub(crate) fn take_wait_site(&self) -> Option<WaitSite> {
+        self.wait_site.lock().take()
+    }
+
+    /// 持有该锁期间，阻塞中的任务无法清除记录
+    #[inline]
+    pub(crate) fn wait_site_lock(&self) -> SpinNoIrqOnlyGuard<'_, Option<WaitSite>> {
+        self.wait_site.lock()
+    }
+
+    #[inline]
+    pub(crate) fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+
     #[inline]
     /// lock the task state and ctx_ptr access
     pub(crate) fn state_lock_manual(&self) -> ManuallyDrop<SpinNoIrqOnlyGuard<TaskState>> {
@@ -168,10 +426,17 @@ impl TaskInner {
 
     #[inline]
     /// get the state of the task
-    pub(crate) fn state(&self) -> TaskState {
+    pub fn state(&self) -> TaskState {
         *self.state.lock()
     }
 
+    /// 尝试获取任务的状态，状态的锁被占用时返回None
+    /// 用于不能等待锁的场合（如panic处理函数）
+    #[inline]
+    pub(crate) fn try_state(&self) -> Option<TaskState> {
+        self.state.try_lock().map(|state| *state)
+    }
+
     #[inline]
     /// set the state of the task
     pub(crate) fn set_state(&self, state: TaskState) {
@@ -180,14 +445,20 @@ impl TaskInner {
 
     /// Whether the task is Exited
     #[inline]
-    pub(crate) fn is_exited(&self) -> bool {
+    pub fn is_exited(&self) -> bool {
         matches!(self.state(), TaskState::Exited)
     }
 
-    /// Whether the task is runnalbe
+    /// Whether the task is running
     #[inline]
-    pub(crate) fn is_runable(&self) -> bool {
-        matches!(self.state(), TaskState::Runable)
+    pub(crate) fn is_running(&self) -> bool {
+        matches!(self.state(), TaskState::Running)
+    }
+
+    /// Whether the task is ready
+    #[inline]
+    pub(crate) fn is_ready(&self) -> bool {
+        matches!(self.state(), TaskState::Ready)
     }
 
     /// Whether the task is blocking
@@ -207,6 +478,181 @@ impl TaskInner {
         self.exit_code.store(exit_code, Ordering::Release)
     }
 
+    /// 任务的返回值，任务还未退出时为None
+    #[inline]
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code_in(self.state())
+    }
+
+    /// 任务处于`state`状态时的返回值，用于已经取得任务状态的场合
+    #[inline]
+    pub(crate) fn exit_code_in(&self, state: TaskState) -> Option<i32> {
+        match state {
+            TaskState::Exited => Some(self.exit_code.load(Ordering::Acquire)),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn join_queue(&self) -> &SpinNoIrq<BlockQueue> {
+        &self.join_queue
+    }
+
+    /// 开始一次带超时的阻塞，返回该次阻塞的序号，超时事件需凭此序号认领超时
+    pub(crate) fn begin_timed_wait(&self) -> u64 {
+        let seq = (self.timed_wait.load(Ordering::Acquire) >> 2) + 1;
+        self.timed_wait.store((seq << 2) | TIMED_WAIT_WAITING, Ordering::Release);
+        seq
+    }
+
+    /// 序号为`seq`的带超时阻塞是否仍在进行
+    pub(crate) fn is_timed_waiting(&self, seq: u64) -> bool {
+        self.timed_wait.load(Ordering::Acquire) == (seq << 2) | TIMED_WAIT_WAITING
+    }
+
+    /// 超时事件将任务移出阻塞队列后调用，返回值代表序号为`seq`的阻塞是否由该超时事件结束
+    pub(crate) fn claim_timeout(&self, seq: u64) -> bool {
+        self.timed_wait.compare_exchange(
+            (seq << 2) | TIMED_WAIT_WAITING,
+            (seq << 2) | TIMED_WAIT_TIMED_OUT,
+            Ordering::AcqRel,
+            Ordering::Acquire
+        ).is_ok()
+    }
+
+    /// 结束带超时的阻塞，返回值代表阻塞是否因超时而结束
+    pub(crate) fn finish_timed_wait(&self) -> bool {
+        let value = self.timed_wait.fetch_and(!TIMED_WAIT_STATE_MASK, Ordering::AcqRel);
+        value & TIMED_WAIT_STATE_MASK == TIMED_WAIT_TIMED_OUT
+    }
+
+    /// 切换过程中调用，返回值代表任务是否被`kill`、且可以在此时退出
+    /// 持有可睡眠锁的线程不能退出，终止请求保留到其释放所有锁之后的切换
+    #[inline]
+    pub(crate) fn take_pending_kill(&self) -> bool {
+        !self.holds_locks() && self.pending_kill.swap(false, Ordering::AcqRel)
+    }
+
+    #[inline]
+    pub(crate) fn lock_acquired(&self) {
+        self.held_locks.fetch_add(1, Ordering::AcqRel);
+    }
+
+    #[inline]
+    pub(crate) fn lock_released(&self) {
+        self.held_locks.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// 线程是否持有可睡眠的锁
+    /// 线程被终止时，其栈上的锁守卫不会被析构，因此持有锁的线程不能被立即终止；协程的Future析构时会释放其持有的锁。
+    #[inline]
+    fn holds_locks(&self) -> bool {
+        self.is_thread() && self.held_locks.load(Ordering::Acquire) > 0
+    }
+
+    #[inline]
+    pub(crate) fn set_requeue_to_global(&self) {
+        self.requeue_to_global.store(true, Ordering::Release)
+    }
+
+    #[inline]
+    pub(crate) fn take_requeue_to_global(&self) -> bool {
+        self.requeue_to_global.swap(false, Ordering::AcqRel)
+    }
+
+    #[inline]
+    pub(crate) fn set_preempted(&self) {
+        self.preempted.store(true, Ordering::Release)
+    }
+
+    #[inline]
+    pub(crate) fn take_preempted(&self) -> bool {
+        self.preempted.swap(false, Ordering::AcqRel)
+    }
+
+    /// 每次poll协程前调用，清除上一次poll的等待请求与唤醒记录
+    #[inline]
+    pub(crate) fn begin_poll(&self) {
+        self.park_requested.store(false, Ordering::Release);
+        self.notified.store(false, Ordering::Release);
+    }
+
+    /// 由以任务自身的Waker轮询的Future调用，请求在poll返回后阻塞任务，直到Waker被唤醒
+    /// 可以在一次poll中被多个Future重复调用
+    #[inline]
+    pub(crate) fn request_park(&self) {
+        self.park_requested.store(true, Ordering::Release);
+    }
+
+    /// 在切换过程中、持有状态锁时调用，返回值代表任务是否请求了阻塞且在poll期间未被唤醒
+    #[inline]
+    pub(crate) fn take_park(&self) -> bool {
+        let park_requested = self.park_requested.swap(false, Ordering::AcqRel);
+        let notified = self.notified.swap(false, Ordering::AcqRel);
+        park_requested && !notified
+    }
+
+    /// 任务开始在CPU上运行，`now`为当前time寄存器的值
+    pub(crate) fn account_switch_in(&self, now: u64) {
+        self.on_cpu_since.store(now, Ordering::Release);
+    }
+
+    /// 任务停止在CPU上运行，累计本次的运行时间，并按切换原因计数
+    pub(crate) fn account_switch_out(&self, now: u64, preempted: bool) {
+        let since = self.on_cpu_since.swap(0, Ordering::AcqRel);
+        // original任务在创建前就已经在运行，不统计其第一次运行的时间
+        if since != 0 {
+            self.runtime_ticks.fetch_add(now.saturating_sub(since), Ordering::AcqRel);
+        }
+        if preempted {
+            self.preemptions.fetch_add(1, Ordering::AcqRel);
+        }
+        else {
+            self.voluntary_switches.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// 累计运行时间（time寄存器的tick数），包含正在进行的一次运行
+    pub(crate) fn runtime_ticks(&self, now: u64) -> u64 {
+        let since = self.on_cpu_since.load(Ordering::Acquire);
+        let running = if since != 0 { now.saturating_sub(since) } else { 0 };
+        self.runtime_ticks.load(Ordering::Acquire) + running
+    }
+
+    #[cfg(feature = "hpm")]
+    #[inline]
+    pub(crate) fn hpm(&self) -> &HpmAccount {
+        &self.hpm
+    }
+
+    /// 任务的运行时间与切换次数统计
+    pub(crate) fn stats(&self) -> TaskStats {
+        TaskStats {
+            runtime: timer::ticks_to_duration(self.runtime_ticks(timer::current_ticks() as u64)),
+            voluntary_switches: self.voluntary_switches.load(Ordering::Acquire),
+            preemptions: self.preemptions.load(Ordering::Acquire),
+            wakeups: self.wakeups.load(Ordering::Acquire),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn cpu_set(&self) -> u64 {
+        self.cpu_set.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn set_cpu_set(&self, cpu_set: u64) {
+        self.cpu_set.store(cpu_set, Ordering::Release)
+    }
+
+    /// 任务能否在id为`cpu_id`的CPU上运行
+    /// CPU的数量在初始化时被限制为不超过64个，因此位图可以表示所有CPU
+    #[inline]
+    pub(crate) fn allows_cpu(&self, cpu_id: usize) -> bool {
+        assert!(cpu_id < u64::BITS as usize, "invalid cpu id {}", cpu_id);
+        self.cpu_set() & (1 << cpu_id) != 0
+    }
+
     #[inline]
     pub(crate) fn set_ctx_ref(&self, ctx_ref: *mut TaskContext) {
         self.ctx_ref.store(NonNull::new(ctx_ref).unwrap());
@@ -237,6 +683,27 @@ impl TaskInner {
         self.owned_stack.swap(new_stack)
     }
 
+    #[cfg(feature = "stack_stats")]
+    #[inline]
+    pub(crate) fn record_stack_peak(&self, usage: usize) -> usize {
+        self.stack_peak.fetch_max(usage, Ordering::AcqRel).max(usage)
+    }
+
+    /// 测量挂起的任务持有的栈的最大使用量，并返回记录的最大值
+    /// 持有状态锁，从而保证Ready或Blocked状态的任务不会在测量期间被恢复执行
+    #[cfg(feature = "stack_stats")]
+    pub(crate) fn measure_stack_peak(&self) -> usize {
+        let state = self.state_lock();
+        if matches!(*state, TaskState::Ready | TaskState::Blocked) {
+            if let Some(stack) = self.swap_owned_stack(None) {
+                self.record_stack_peak(stack.peak_usage());
+                self.swap_owned_stack(Some(stack));
+            }
+        }
+        drop(state);
+        self.stack_peak.load(Ordering::Acquire)
+    }
+
     #[inline]
     pub(crate) fn get_future(&self) -> *mut Pin<Box<dyn Future<Output = ()> + Send>> {
         self.future.as_ptr()
@@ -267,90 +734,263 @@ impl TaskInner {
 
 /// pub(crate)方法
 impl TaskInner {
-    pub(crate) fn new<F>(func: F) -> Arc<Task>
+    pub(crate) fn new_with_options<F>(func: F, options: TaskOptions) -> Arc<Task>
     where F: (FnOnce() -> i32) + Send + 'static {
-        Self::new_raw(func, false, false, false)
+        Self::new_raw(func, options, false, false, false)
     }
 
-    pub(crate) fn new_async<F>(func: F) -> Arc<Task>
+    pub(crate) fn new_async_with_options<F>(func: F, options: TaskOptions) -> Arc<Task>
     where F: Future<Output = i32> + Send + 'static {
-        Self::new_async_raw(func, false, false, false)
+        Self::new_async_raw(func, options, false, false, false)
     }
 
     pub(crate) fn new_idle() -> Arc<Task> {
         Self::new_async_raw(poll_fn(|_| -> Poll<i32> {
             debug!("run idle task");
             Poll::Pending
-        }), true, false, false)
+        }), TaskOptions::default(), true, false, false)
     }
 
     pub(crate) fn new_init<F>(func: F) -> Arc<Task>
     where F: (FnOnce() -> i32) + Send + 'static {
-        Self::new_raw(func, false, true, false)
+        Self::new_raw(func, TaskOptions::default(), false, true, false)
     }
 
     pub(crate) fn new_async_init<F>(func: F) -> Arc<Task>
     where F: Future<Output = i32> + Send + 'static {
-        Self::new_async_raw(func, false, true, false)
+        Self::new_async_raw(func, TaskOptions::default(), false, true, false)
     }
 
     pub(crate) fn new_original() -> Arc<Task> {
-        Self::new_raw(|| { 0 }, false, false, true)
+        Self::new_raw(|| { 0 }, TaskOptions::default(), false, false, true)
     }
 
     pub(crate) fn wakeup(self: Arc<AxTask<Self>>) {
+        self.wakeup_to(false);
+    }
+
+    /// 唤醒任务。`to_global`代表任务需要放回调度器时，放入全局调度器还是当前CPU的调度器。
+    /// 处于Blocking状态的任务还未完成切换，因此只修改其状态，由切换过程将其放回调度器。
+    /// 返回值代表任务是否被唤醒（任务原本已经是Running或Ready状态时返回false）。
+    pub(crate) fn wakeup_to(self: Arc<AxTask<Self>>, to_global: bool) -> bool {
         let mut state = self.state_lock_manual();
+        if matches!(**state, TaskState::Blocking | TaskState::Blocked) {
+            self.set_waiting_on(0);
+            self.wakeups.fetch_add(1, Ordering::AcqRel);
+        }
+        #[cfg(feature = "trace")]
+        if matches!(**state, TaskState::Blocking | TaskState::Blocked) {
+            trace::record(Processor::current_id(), TraceEvent::Wake, self.id(), to_global as u64);
+        }
         match **state {
-            TaskState::Blocking => **state = TaskState::Runable,
-            TaskState::Runable => (),
+            TaskState::Blocking => **state = TaskState::Running,
+            TaskState::Running | TaskState::Ready => {
+                // 正在运行的协程可能已经请求了阻塞，记录唤醒，使其在poll返回后不被阻塞
+                if matches!(**state, TaskState::Running) {
+                    self.notified.store(true, Ordering::Release);
+                }
+                ManuallyDrop::into_inner(state);
+                return false;
+            }
             TaskState::Blocked => {
                 // debug!("task unblock: {}", self.id());
-                **state = TaskState::Runable;
+                **state = TaskState::Ready;
                 ManuallyDrop::into_inner(state);
                 // may be other processor wake up
                 Processor::with_current(|processor| {
-                    processor.add_task_to_local(self);
+                    if to_global {
+                        processor.add_task_to_global(self);
+                    }
+                    else {
+                        processor.add_task_to_local(self);
+                    }
                 });
-                return;
+                return true;
+            }
+            // 被`kill`终止的任务可能仍位于阻塞队列或定时器中
+            TaskState::Exited => {
+                ManuallyDrop::into_inner(state);
+                return false;
             }
-            _ => panic!("unexpect state when wakeup_task"),
         }
         ManuallyDrop::into_inner(state);
+        true
+    }
+}
+
+/// `TaskInner::mark_killed`的结果
+pub(crate) enum KillResult {
+    /// 任务已经退出
+    AlreadyExited,
+    /// 任务正在运行或持有可睡眠的锁，将在之后的切换时退出
+    Deferred,
+    /// 任务已被设为Exited状态，`was_ready`代表其是否可能位于调度器中
+    Exited { was_ready: bool },
+}
+
+/// 任务终止与资源回收
+impl TaskInner {
+    /// 将任务标记为被终止
+    /// 阻塞或就绪的任务不在执行，因此直接设为Exited状态，由调用者将其移出阻塞队列；正在运行（包括Blocking状态）的任务则在下一次切换时退出。
+    /// 持有可睡眠锁的线程在释放所有锁之后的切换时退出。
+    pub(crate) fn mark_killed(&self, exit_code: i32) -> KillResult {
+        let mut state = self.state_lock();
+        match *state {
+            TaskState::Exited => KillResult::AlreadyExited,
+            TaskState::Running | TaskState::Blocking => {
+                self.set_exit_code(exit_code);
+                self.pending_kill.store(true, Ordering::Release);
+                KillResult::Deferred
+            }
+            TaskState::Ready | TaskState::Blocked if self.holds_locks() => {
+                self.set_exit_code(exit_code);
+                self.pending_kill.store(true, Ordering::Release);
+                KillResult::Deferred
+            }
+            TaskState::Ready | TaskState::Blocked => {
+                let was_ready = matches!(*state, TaskState::Ready);
+                self.set_exit_code(exit_code);
+                self.set_waiting_on(0);
+                *state = TaskState::Exited;
+                KillResult::Exited { was_ready }
+            }
+        }
+    }
+
+    /// 调度器取出任务后调用，将其由Ready状态改为Running状态
+    /// 返回false代表任务在就绪期间被终止，应被跳过
+    /// 使用MOIC时，外部中断与IPC会直接将注册的任务加入就绪队列，因此取出的任务也可能处于其它状态。
+    pub(crate) fn claim_run(&self) -> bool {
+        let mut state = self.state_lock();
+        match *state {
+            TaskState::Ready => {
+                *state = TaskState::Running;
+                true
+            }
+            TaskState::Exited => false,
+            // 被MOIC唤醒
+            #[cfg(any(feature = "moic", feature = "moic_soft"))]
+            TaskState::Blocked => {
+                self.set_waiting_on(0);
+                self.wakeups.fetch_add(1, Ordering::AcqRel);
+                #[cfg(feature = "trace")]
+                trace::record(Processor::current_id(), TraceEvent::Wake, self.id(), 0);
+                *state = TaskState::Running;
+                true
+            }
+            // 任务还未完成阻塞就被MOIC唤醒，与`wakeup_to`相同，只修改其状态，由切换过程将其放回调度器
+            #[cfg(any(feature = "moic", feature = "moic_soft"))]
+            TaskState::Blocking => {
+                self.set_waiting_on(0);
+                self.wakeups.fetch_add(1, Ordering::AcqRel);
+                *state = TaskState::Running;
+                false
+            }
+            // 任务正在运行，唤醒无效
+            #[cfg(any(feature = "moic", feature = "moic_soft"))]
+            TaskState::Running => false,
+            #[cfg(not(any(feature = "moic", feature = "moic_soft")))]
+            _ => panic!("unexpect state when pick_next_task"),
+        }
+    }
+
+    /// 释放已退出任务的Future和任务局部变量，并取出其持有的栈（由调用者放回栈池）
+    /// 需在任务不再执行、且不持有Processor锁时调用，因为Future的析构过程可能使用任务管理的接口。
+    pub(crate) fn reap(&self) -> Option<Arc<TaskStack>> {
+        // 任务局部变量在释放锁之后析构，其析构过程可能使用任务管理的接口
+        let locals = core::mem::take(&mut *self.locals.lock());
+        drop(locals);
+        let future = self.future.swap(Box::pin(pending()));
+        if self.is_thread() {
+            // 保存了寄存器上下文的任务（线程，或被抢占的协程）的Future正处于poll过程中，且这次poll不会再被恢复。
+            // 此时状态机记录的仍是上一次挂起时的状态，而其中的值可能已被本次poll移动到栈上，析构它会重复析构这些值，因此只释放其内存，不执行析构。
+            // 对于线程，闭包在第一次poll开始时就被移动到栈上，Future中不再持有任何值，因此正常退出的线程不会泄漏；
+            // 只有被终止时还在执行的线程，以及被抢占后被终止的协程，其栈上（及状态机中）持有的值会泄漏，见`kill`。
+            unsafe {
+                let future = Box::into_raw(Pin::into_inner_unchecked(future));
+                let layout = Layout::for_value(&*future);
+                if layout.size() != 0 {
+                    alloc::alloc::dealloc(future as *mut u8, layout);
+                }
+            }
+            self.swap_owned_stack(None)
+        }
+        else {
+            drop(future);
+            None
+        }
     }
 }
 
 /// private方法
 impl TaskInner {
-    fn new_raw<F>(func: F, is_idle: bool, is_init: bool, is_original: bool) -> Arc<Task>
+    fn new_raw<F>(func: F, options: TaskOptions, is_idle: bool, is_init: bool, is_original: bool) -> Arc<Task>
     where F: (FnOnce() -> i32) + Send + 'static {
         Self::new_async_raw_with_wrapped_func(async { // 将线程转化为协程，从而规避线程与协程的启动方式不同的问题 
             let exit_code = func();
             exit_current(exit_code); // 将任务的自然退出方式也统一为使用exit系列函数
-        }, is_idle, is_init, is_original)
+        }, options, TaskKind::Thread, is_idle, is_init, is_original)
     }
 
-    fn new_async_raw<F>(func: F, is_idle: bool, is_init: bool, is_original: bool) -> Arc<Task>
+    fn new_async_raw<F>(func: F, options: TaskOptions, is_idle: bool, is_init: bool, is_original: bool) -> Arc<Task>
     where F: Future<Output = i32> + Send + 'static {
         Self::new_async_raw_with_wrapped_func(async { 
             let exit_code = func.await;
             exit_current_async(exit_code).await; // 将任务的自然退出方式也统一为使用exit系列函数。结果：直属于TaskInner的Future不会返回Ready，只会返回Pending。
-        }, is_idle, is_init, is_original)
+        }, options, TaskKind::Coroutine, is_idle, is_init, is_original)
     }
 
-    fn new_async_raw_with_wrapped_func<F>(func: F, is_idle: bool, is_init: bool, is_original: bool) -> Arc<Task>
+    fn new_async_raw_with_wrapped_func<F>(func: F, options: TaskOptions, kind: TaskKind, is_idle: bool, is_init: bool, is_original: bool) -> Arc<Task>
     where F: Future<Output = ()> + Send + 'static {
-        Arc::new(Task::new(TaskInner {
+        let task = Arc::new(Task::new(TaskInner {
             id: TaskId::new(),
             is_idle,
             is_init,
             is_original,
-            state: SpinNoIrqOnly::new(TaskState::Runable),
+            name: options.name,
+            stack_size: options.stack_size,
+            kind,
+            state: SpinNoIrqOnly::new(if is_original { TaskState::Running } else { TaskState::Ready }), // original任务创建时就在CPU上执行
             exit_code: AtomicI32::new(0),
+            priority: AtomicIsize::new(0),
+            priority_set: AtomicBool::new(false),
+            last_cpu: AtomicUsize::new(usize::MAX),
+            waiting_on: AtomicUsize::new(0),
+            wait_site: SpinNoIrqOnly::new(None),
+            join_queue: SpinNoIrq::new(BlockQueue::new()),
+            timed_wait: AtomicU64::new(TIMED_WAIT_NONE),
+            pending_kill: AtomicBool::new(false),
+            held_locks: AtomicUsize::new(0),
+            requeue_to_global: AtomicBool::new(false),
+            user_data: AtomicUsize::new(0),
+            locals: SpinNoIrqOnly::new(BTreeMap::new()),
+            ext: options.ext,
+            preempted: AtomicBool::new(false),
+            park_requested: AtomicBool::new(false),
+            notified: AtomicBool::new(false),
+            on_cpu_since: AtomicU64::new(0),
+            runtime_ticks: AtomicU64::new(0),
+            voluntary_switches: AtomicU64::new(0),
+            preemptions: AtomicU64::new(0),
+            wakeups: AtomicU64::new(0),
+            #[cfg(feature = "hpm")]
+            hpm: HpmAccount::new(),
+            cpu_set: AtomicU64::new(u64::MAX),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
             future: AtomicCell::new(Box::pin(func)),
             ctx_ref: AtomicCell::new(NonNull::dangling()),
+            #[cfg(feature = "stack_stats")]
+            stack_peak: AtomicUsize::new(0),
             owned_stack: AtomicCell::new(None),
-        }))
+        }));
+        registry::register(&task);
+        task
+    }
+}
+
+impl Drop for TaskInner {
+    fn drop(&mut self) {
+        registry::unregister(self.id());
     }
 }
\ No newline at end of file
@@ -3,11 +3,26 @@ use axlog::{debug, error, info, warn};
 use riscv::register::sstatus;
 use spinlock::SpinNoIrqOnlyGuard;
 use core::{arch::asm, mem::ManuallyDrop, ops::Deref, task::Poll};
-use crate::{processor::{self, Processor}, task::TaskState};
+#[cfg(feature = "trace")]
+use crate::trace::{self, TraceEvent};
+use crate::{processor::{self, Processor}, stack::TASK_STACK_SIZE, task::TaskState, timer, BlockQueue};
 
 // use crate::{current_processor, processor::PrevCtxSave, stack_pool::TaskStack, AxTaskRef, CurrentTask, TaskState};
 use super::{reg_context::{load_next_ctx, save_prev_ctx}, waker::waker_from_task, Task, TaskContext};
 
+/// 由使用该模块的系统实现，在每次任务切换时执行（如切换页表、保存浮点寄存器、更新统计信息）
+/// 钩子在关中断、持有当前CPU的Processor锁时执行，因此其中不能使用任务管理的接口。
+/// 当前任务没有被切换出去（如被抢占但没有其它任务可运行）时不会调用。
+#[cfg(feature = "switch_hook")]
+#[crate_interface::def_interface]
+pub trait TaskSwitchIf {
+    /// 任务停止在CPU上运行，此时其上下文已经保存完成
+    /// 调用时还持有任务的状态锁，任务还未被放回调度器，因此不会同时在其它CPU上被换入
+    fn on_switch_out(prev: &Task, user_data: usize);
+    /// 任务即将开始在CPU上运行（恢复寄存器上下文或被poll之前）
+    fn on_switch_in(next: &Task, user_data: usize);
+}
+
 // #[cfg(feature = "preempt")]
 /// This is only used when the preempt feature is enabled.
 pub(crate) fn preempt_switch_entry(taskctx: &mut TaskContext) {
@@ -20,13 +35,19 @@ pub(crate) fn preempt_switch_entry(taskctx: &mut TaskContext) {
     let prev_task = Processor::with_current(|processor| {
         // processor.acquire_switch_guard();
         // warn!("interupt status when switch: {}", processor.get_sstatus_in_switch_guard());
-        processor.current_task().get_current_ptr()
+        let prev_task = processor.current_task().get_current_ptr();
+        #[cfg(feature = "trace")]
+        trace::record(processor.id(), TraceEvent::Preempt, prev_task.id(), 0);
+        prev_task
     });
 
     // debug
     let id = prev_task.id();
     debug!("into preempt_switch_entry() with prev task {id}");
 
+    prev_task.set_preempted();
+    #[cfg(feature = "hpm")]
+    prev_task.hpm().mark_stop();
     prev_task.set_ctx_ref(taskctx as _);
     unsafe { schedule_with_sp_change(); }
 }
@@ -85,7 +106,8 @@ pub(super) unsafe extern "C" fn schedule_with_sp_change() {
 #[no_mangle]
 fn before_change_stack() -> usize {
     Processor::with_current(|processor| {
-        let new_stack = processor.get_stack_pool_mut().fetch();
+        // 新栈用于运行调度过程，以及之后被poll的协程。若下一任务需要不同大小的栈，会在run_next中再次更换。
+        let new_stack = processor.get_stack_pool_mut().fetch(TASK_STACK_SIZE);
         let new_stack_top = new_stack.top();
         let old_stack = processor.get_stack_pool_mut().swap_curr_stack(Some(new_stack));
         let prev_task = processor.current_task().get_current_ptr();
@@ -96,6 +118,10 @@ fn before_change_stack() -> usize {
 
         // 此时CPU的current_stack必须为Some，但除了从original task切换到其它任务以外（此时CPU使用的栈不被current_stack管理）。
         assert!(old_stack.is_some() || prev_task.is_original());
+        #[cfg(feature = "stack_protect")]
+        if let Some(old_stack) = &old_stack {
+            old_stack.check_overflow(id);
+        }
         // let prev_task = processor.current_task().get_current_ptr();
         assert!(prev_task.swap_owned_stack(old_stack).is_none());
         new_stack_top
@@ -126,7 +152,7 @@ fn exchange_current(mut next_task: Arc<Task>) {
     let id = next_task.id();
     debug!("into exchange_current() with next task {id}");
 
-    Processor::with_current(|processor| {
+    let (exited_task, switched) = Processor::with_current(|processor| {
         let prev_task = processor.current_task().get_current_ptr();
         // // task in a disable_preempt context? it not allowed ctx switch
         // #[cfg(feature = "preempt")]
@@ -138,29 +164,68 @@ fn exchange_current(mut next_task: Arc<Task>) {
         // Here must lock curr state, and no one can change curr state
         // when excuting ctx_switch
         let mut prev_state_lock = prev_task.state_lock_manual();
+        // 此时当前任务的上下文已经保存完成，因此可以安全地放入全局调度器，被其它CPU取出执行
+        let requeue_to_global = prev_task.take_requeue_to_global();
+        // 在运行期间被kill的任务，在此处退出（返回值已在kill时设置）
+        if prev_task.take_pending_kill() && !matches!(**prev_state_lock, TaskState::Exited) {
+            **prev_state_lock = TaskState::Exited;
+        }
+        let preempted = prev_task.take_preempted();
+        let now = timer::current_ticks() as u64;
+        // 换出当前任务：统计运行时间、记录硬件计数器并调用钩子
+        // 需要在当前任务被放回调度器、或被设为Blocked之前（仍持有其状态锁时）进行，否则其可能已被其它CPU取出并换入
+        let switch_out = |prev_task: &Arc<Task>| {
+            prev_task.account_switch_out(now, preempted);
+            #[cfg(feature = "trace")]
+            trace::record(processor.id(), TraceEvent::SwitchOut, prev_task.id(), preempted as u64);
+            #[cfg(feature = "hpm")]
+            prev_task.hpm().switch_out();
+            #[cfg(feature = "switch_hook")]
+            crate_interface::call_interface!(TaskSwitchIf::on_switch_out(prev_task, prev_task.user_data()));
+        };
+        let mut exited_task = None;
         loop {
             match **prev_state_lock {
-                TaskState::Runable => {
-                    if next_task.is_idle() {
+                // 等待Waker的协程在poll期间没有被唤醒，则将其阻塞（被抢占时poll还未结束，不进行阻塞）
+                TaskState::Running if !preempted && prev_task.take_park() => {
+                    **prev_state_lock = TaskState::Blocking;
+                }
+                TaskState::Running => {
+                    // 若当前任务的亲和性已被修改为不允许在该CPU上运行，则不能继续运行它，而是将其放回调度器（会被转移到全局调度器）
+                    if next_task.is_idle() && prev_task.allows_cpu(processor.id()) {
                         next_task = prev_task.clone();
                         break;
                     }
+                    switch_out(&prev_task);
+                    **prev_state_lock = TaskState::Ready;
                     if !prev_task.is_idle() {
                         // #[cfg(feature = "preempt")]
                         // current_processor()
                         //     .put_prev_task(prev_task.clone(), prev_task.get_preempt_pending());
                         // #[cfg(not(feature = "preempt"))]
                         // current_processor().put_prev_task(prev_task.clone(), false);
-                        processor.add_task_to_local(prev_task.clone());
+                        if requeue_to_global {
+                            processor.add_task_to_global(prev_task.clone());
+                        }
+                        else {
+                            processor.add_task_to_local(prev_task.clone());
+                        }
                     }
                     break;
                 }
                 TaskState::Blocking => {
                     // debug!("task block: {}", prev_task.id_name());
+                    #[cfg(feature = "trace")]
+                    trace::record(processor.id(), TraceEvent::Block, prev_task.id(), 0);
+                    switch_out(&prev_task);
                     **prev_state_lock = TaskState::Blocked;
                     break;
                 }
                 TaskState::Exited => {
+                    #[cfg(feature = "trace")]
+                    trace::record(processor.id(), TraceEvent::Exit, prev_task.id(), 0);
+                    switch_out(&prev_task);
+                    exited_task = Some(prev_task.clone());
                     break;
                 }
                 _ => {
@@ -170,20 +235,52 @@ fn exchange_current(mut next_task: Arc<Task>) {
         }
         ManuallyDrop::into_inner(prev_state_lock);
 
+        // 统计换入的任务与CPU的运行时间，继续运行当前任务时不视为切换（当前任务也没有被换出）
+        if !Arc::ptr_eq(&prev_task, &next_task) {
+            next_task.account_switch_in(now);
+            processor.account_cpu_time(now, prev_task.is_idle(), next_task.is_idle());
+            #[cfg(feature = "trace")]
+            trace::record(processor.id(), TraceEvent::SwitchIn, next_task.id(), 0);
+            #[cfg(feature = "hpm")]
+            next_task.hpm().switch_in();
+        }
+        else {
+            #[cfg(feature = "hpm")]
+            prev_task.hpm().cancel_stop();
+        }
+
+        // 从调度器取出的任务已在pick_next_task中设为Running状态，idle任务则不经过调度器
+        if next_task.is_idle() && !Arc::ptr_eq(&prev_task, &next_task) {
+            next_task.set_state(TaskState::Running);
+        }
+
+        let switched = !Arc::ptr_eq(&prev_task, &next_task);
+        next_task.set_last_cpu(processor.id());
         processor.current_task().replace_current(next_task);
+        (exited_task, switched)
     });
 
+    // 回收退出的任务的资源，并唤醒等待该任务退出的任务
+    // 需要在释放Processor的锁之后进行：Future的析构过程可能使用任务管理的接口，且唤醒操作需要获取Processor的锁。
+    // 此时退出的任务已经不会再被执行，因此其不会因被抢占而错过唤醒
+    if let Some(exited_task) = exited_task {
+        // 在阻塞过程中被终止的任务仍位于阻塞队列中
+        BlockQueue::cancel_wait(&exited_task);
+        Processor::reap_task(&exited_task);
+        exited_task.join_queue().lock().wake_all_to_local();
+    }
 
     // #[cfg(feature = "preempt")]
     // // reset preempt pending
     // next_task.set_preempt_pending(false);
 
-    run_next();
+    run_next(switched);
 }
 
 /// Run next task
+/// `switched`代表当前任务是否是刚刚切换进来的（而不是继续运行原任务）
 #[no_mangle]
-fn run_next() {
+fn run_next(switched: bool) {
     // SAFETY: INIT when switch_to
     // First into task entry, manually perform the subsequent work of switch_to
 
@@ -198,6 +295,15 @@ fn run_next() {
     let id = next_task.id();
     debug!("into run_next() with next task {id}");
 
+    #[cfg(feature = "switch_hook")]
+    if switched {
+        Processor::with_current(|_processor| {
+            crate_interface::call_interface!(TaskSwitchIf::on_switch_in(&next_task, next_task.user_data()));
+        });
+    }
+    #[cfg(not(feature = "switch_hook"))]
+    let _ = switched;
+
     if next_task.is_thread() {
         let task_ctx_ref = next_task.get_ctx_ref();
         // Dangerous: the current stack will be recycled. 
@@ -205,11 +311,13 @@ fn run_next() {
         Processor::with_current(|processor| {
             let new_stack = next_task.swap_owned_stack(None);
             assert!(new_stack.is_some());
+            #[cfg(feature = "stack_protect")]
+            new_stack.as_ref().unwrap().check_overflow(id);
             let old_stack = processor.get_stack_pool_mut().swap_curr_stack(new_stack);
             // original_task持有的栈不被processor数据结构管理
             assert!(old_stack.is_some() || next_task.is_original());
             if old_stack.is_some() {
-                unsafe { processor.get_stack_pool_mut().recycle_stack(old_stack.unwrap()); }
+                unsafe { processor.get_stack_pool_mut().recycle_curr_stack(old_stack.unwrap()); }
             }
         });
 
@@ -218,6 +326,8 @@ fn run_next() {
         unsafe {
             sstatus::set_sie();
         }
+        // load_next_ctx不会返回，因此需要先释放当前栈上持有的引用（任务仍被CurrentTask持有）
+        drop(next_task);
         unsafe {
             // // debug
             // error!("task {id} load context:");
@@ -225,20 +335,75 @@ fn run_next() {
             load_next_ctx(&mut *task_ctx_ref);
         }
     } else {
-        let waker = waker_from_task(next_task.clone());
-        let mut cx = core::task::Context::from_waker(&waker);
-        let future = unsafe { &mut *next_task.get_future() };
-
-        // 在准备返回任务时开中断
-        #[cfg(feature = "irq")]
-        unsafe {
-            sstatus::set_sie();
-        }
-        assert!(future.as_mut().poll(&mut cx).is_pending());
-        // 此处，任务执行后回到任务模块，因此需要再次关中断。
-        #[cfg(feature = "irq")]
-        unsafe {
-            sstatus::clear_sie();
+        // 协程在当前栈上运行，若当前栈的大小不符合任务的要求，则先更换栈
+        let stack_size = next_task.stack_size();
+        let new_stack_top = Processor::with_current(|processor| {
+            let stack_pool = processor.get_stack_pool_mut();
+            if stack_pool.curr_stack_size() == Some(stack_size) {
+                return None;
+            }
+            let new_stack = stack_pool.fetch(stack_size);
+            let new_stack_top = new_stack.top();
+            // Dangerous: the current stack will be recycled.
+            // But it is used until executing the `run_next_with_sp_change` function.
+            if let Some(old_stack) = stack_pool.swap_curr_stack(Some(new_stack)) {
+                unsafe { stack_pool.recycle_curr_stack(old_stack); }
+            }
+            Some(new_stack_top)
+        });
+        if let Some(new_stack_top) = new_stack_top {
+            // 换栈后不会再返回，因此需要先释放当前栈上持有的引用
+            drop(next_task);
+            unsafe { run_next_with_sp_change(new_stack_top); }
         }
+        poll_next();
     }
 }
+
+/// 换栈后poll下一任务，之后在新栈上继续调度循环（与`schedule_with_sp_change`相同）
+#[naked]
+unsafe extern "C" fn run_next_with_sp_change(new_stack_top: usize) -> ! {
+    asm!(
+        "
+        mv      sp, a0
+        call    {poll_next}
+        ",
+        "
+        jal     ra, {schedule_without_sp_change}
+        j       -4
+        ",
+        poll_next = sym poll_next,
+        schedule_without_sp_change = sym schedule_without_sp_change,
+        options(noreturn),
+    );
+}
+
+/// poll当前任务（协程）一次
+#[no_mangle]
+extern "C" fn poll_next() {
+    let next_task = Processor::with_current(|processor| {
+        processor.current_task().get_current_ptr()
+    });
+    let waker = waker_from_task(next_task.clone());
+    let mut cx = core::task::Context::from_waker(&waker);
+    let future = unsafe { &mut *next_task.get_future() };
+    next_task.begin_poll();
+
+    // 在准备返回任务时开中断
+    #[cfg(feature = "irq")]
+    unsafe {
+        sstatus::set_sie();
+    }
+    assert!(future.as_mut().poll(&mut cx).is_pending());
+    // 此处，任务执行后回到任务模块，因此需要再次关中断。
+    #[cfg(feature = "irq")]
+    unsafe {
+        sstatus::clear_sie();
+    }
+
+    // 协程在当前栈上运行，因此在poll之后检查当前栈
+    #[cfg(feature = "stack_protect")]
+    Processor::with_current(|processor| {
+        processor.get_stack_pool_mut().check_curr_stack_overflow(next_task.id());
+    });
+}
@@ -6,18 +6,25 @@ use alloc::sync::Arc;
 
 use core::task::{RawWaker, RawWakerVTable, Waker};
 
-const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
 
+// Waker可能被复制并存放在定时器等位置，因此复制时需要增加任务的引用计数
 unsafe fn clone(p: *const ()) -> RawWaker {
+    Arc::increment_strong_count(p as *const Task);
     RawWaker::new(p, &VTABLE)
 }
 
-/// nop
-// 疑问：这里的wake函数是否会释放掉持有的任务的Arc？如果是的话，它是否不能直接作为wake_by_ref函数？
+/// 消耗Waker持有的任务引用
 unsafe fn wake(p: *const ()) { 
     Arc::from_raw(p as *const Task).wakeup();
 }
 
+/// 不消耗Waker持有的任务引用
+unsafe fn wake_by_ref(p: *const ()) {
+    Arc::increment_strong_count(p as *const Task);
+    Arc::from_raw(p as *const Task).wakeup();
+}
+
 unsafe fn drop(p: *const ()) {
     // nop
     Arc::from_raw(p as *const Task);
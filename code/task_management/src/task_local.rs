@@ -0,0 +1,61 @@
+//! 任务局部存储
+//! 数据保存在任务的控制块中，而不是`tp`寄存器指向的区域，因此同时适用于线程和协程，
+//! 且不受协程被抢占后转为以寄存器上下文保存的影响。数据在任务退出、资源被回收时析构。
+
+use alloc::boxed::Box;
+
+use crate::current_ptr;
+
+/// 任务局部变量，通过`task_local!`宏定义
+/// 每个任务在第一次访问时用初始化函数创建自己的副本。
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    /// 以当前任务的副本调用`f`
+    /// 只提供共享引用，需要修改时可使用`Cell`、`RefCell`等类型。
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where F: FnOnce(&T) -> R {
+        let key = self as *const Self as usize;
+        let current = current_ptr();
+        let value = match current.task_local(key) {
+            Some(value) => value,
+            // 初始化函数在不持有锁时执行，其中可以访问其它任务局部变量
+            None => current.insert_task_local(key, Box::new((self.init)())),
+        };
+        // SAFETY: 数据被装箱保存，插入其它变量不会使其移动；数据只在任务退出后才被释放，
+        // 而`f`在当前任务中执行，因此在`f`执行期间数据一直有效
+        f(unsafe { &*(value as *const T) })
+    }
+}
+
+/// 定义任务局部变量，用法与标准库的`thread_local!`相同
+/// ```ignore
+/// task_local! {
+///     static COUNTER: Cell<usize> = Cell::new(0);
+/// }
+/// COUNTER.with(|counter| counter.set(counter.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::task_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::LocalKey<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::LocalKey::new(__init)
+        };
+    };
+}
@@ -0,0 +1,171 @@
+use core::{future::Future, pin::Pin, sync::atomic::{AtomicUsize, Ordering}, task::{Context, Poll, Waker}, time::Duration};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use spinlock::SpinNoIrq;
+use riscv::register::time;
+
+use crate::{processor::Processor, task::waker_from_task};
+
+/// time寄存器递增的频率（Hz），由嵌入该模块的系统（或trap_handler模块）在初始化时设置
+static TIMEBASE_FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+
+/// 定时器到期时执行的回调
+pub(crate) type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// 定时器列表中的事件的键，用于取消事件
+pub(crate) type TimerKey = (usize, u64);
+
+/// 按截止时间排序的定时器列表，每个CPU持有一个。
+/// 键中的序号用于区分截止时间相同的事件。
+pub(crate) struct TimerList {
+    events: BTreeMap<TimerKey, TimerCallback>,
+    next_seq: u64,
+}
+
+impl TimerList {
+    pub(crate) const fn new() -> Self {
+        Self {
+            events: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// 加入一个在`deadline`（time寄存器的值）到期的事件
+    pub(crate) fn add(&mut self, deadline: usize, callback: TimerCallback) -> TimerKey {
+        let key = (deadline, self.next_seq);
+        self.events.insert(key, callback);
+        self.next_seq = self.next_seq.wrapping_add(1);
+        key
+    }
+
+    /// 取消还未到期的事件，返回值代表事件是否还在列表中
+    pub(crate) fn cancel(&mut self, key: TimerKey) -> bool {
+        self.events.remove(&key).is_some()
+    }
+
+    /// 取出所有在`now`之前（含）到期的事件
+    pub(crate) fn take_expired(&mut self, now: usize) -> Vec<TimerCallback> {
+        let pending = self.events.split_off(&(now + 1, 0));
+        let expired = core::mem::replace(&mut self.events, pending);
+        expired.into_values().collect()
+    }
+}
+
+pub(crate) fn set_timebase_frequency(frequency: usize) {
+    TIMEBASE_FREQUENCY.store(frequency, Ordering::Release);
+}
+
+/// 未通过`init_timebase_frequency`设置频率时使用的默认值（Hz），与QEMU virt平台的频率相同
+/// 在其它平台上，未设置频率时时间的换算（睡眠时长、运行时间统计等）会不准确，但不会出错。
+pub const DEFAULT_TIMEBASE_FREQUENCY: usize = 10_000_000;
+
+pub(crate) fn timebase_frequency() -> usize {
+    match TIMEBASE_FREQUENCY.load(Ordering::Acquire) {
+        0 => DEFAULT_TIMEBASE_FREQUENCY,
+        frequency => frequency,
+    }
+}
+
+/// 当前time寄存器的值
+#[inline]
+pub(crate) fn current_ticks() -> usize {
+    time::read()
+}
+
+pub(crate) fn duration_to_ticks(duration: Duration) -> usize {
+    (duration.as_nanos() * timebase_frequency() as u128 / 1_000_000_000) as usize
+}
+
+pub(crate) fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos((ticks as u128 * 1_000_000_000 / timebase_frequency() as u128) as u64)
+}
+
+/// 执行当前CPU上所有已到期的事件
+/// 回调中可能会唤醒任务（从而再次获取Processor），因此需要在释放Processor后执行。
+pub(crate) fn check_timers_current() {
+    let now = current_ticks();
+    let expired = Processor::with_current(|processor| {
+        processor.with_timer_list(|timer_list| timer_list.take_expired(now))
+    });
+    for callback in expired {
+        callback();
+    }
+}
+
+/// 睡眠的Future，在截止时间到达后返回Ready
+/// 第一次轮询时在当前CPU的定时器列表中注册一个事件，之后的轮询只更新其唤醒的Waker；若由任务自身的Waker轮询（即直接在任务中await），
+/// 则请求任务在poll返回后阻塞，直到被唤醒（而不是被反复轮询），因此可以与其它Future组合使用。
+/// 完成或被析构时取消事件：事件只以弱引用持有Waker，因此即使事件位于其它CPU上而无法移除，也不会再唤醒任务。
+pub struct Sleep {
+    deadline: usize,
+    /// 事件到期时唤醒的Waker
+    waker: Arc<SpinNoIrq<Option<Waker>>>,
+    /// 注册的事件所在的CPU及其键
+    timer: Option<(usize, TimerKey)>,
+}
+
+impl Sleep {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            deadline: current_ticks() + duration_to_ticks(duration),
+            waker: Arc::new(SpinNoIrq::new(None)),
+            timer: None,
+        }
+    }
+
+    /// 取消注册的事件
+    fn cancel(&mut self) {
+        self.waker.lock().take();
+        if let Some((cpu_id, key)) = self.timer.take() {
+            Processor::with_current(|processor| {
+                if processor.id() == cpu_id {
+                    processor.with_timer_list(|timer_list| timer_list.cancel(key));
+                }
+            });
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if current_ticks() >= self.deadline {
+            self.cancel();
+            return Poll::Ready(());
+        }
+        // waker作用域
+        {
+            let mut waker = self.waker.lock();
+            if !waker.as_ref().is_some_and(|waker| waker.will_wake(cx.waker())) {
+                *waker = Some(cx.waker().clone());
+            }
+        }
+        let deadline = self.deadline;
+        let waker = Arc::downgrade(&self.waker);
+        let timer = Processor::with_current(|processor| {
+            let current = processor.current_task().get_current_ptr();
+            if cx.waker().will_wake(&waker_from_task(current.clone())) {
+                current.request_park();
+            }
+            self.timer.is_none().then(|| {
+                let key = processor.with_timer_list(|timer_list| timer_list.add(deadline, Box::new(move || {
+                    let waker = waker.upgrade().and_then(|waker| waker.lock().take());
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                })));
+                (processor.id(), key)
+            })
+        });
+        if timer.is_some() {
+            self.timer = timer;
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
@@ -0,0 +1,189 @@
+//! 调度事件的跟踪记录
+//! 每个CPU持有一个无锁的环形缓冲区，事件记录在产生事件的CPU的缓冲区中，旧的事件会被新的事件覆盖。
+//! 缓冲区可以导出为Chrome trace event格式的JSON，用Perfetto或chrome://tracing查看。
+
+use core::{fmt::{self, Write}, sync::atomic::{fence, AtomicU64, Ordering}};
+use alloc::vec::Vec;
+use lazy_init::LazyInit;
+
+use crate::timer;
+
+/// 每个CPU的缓冲区能容纳的事件数
+pub(crate) const TRACE_BUFFER_LEN: usize = 4096;
+
+static TRACE_BUFFERS: LazyInit<Vec<TraceBuffer>> = LazyInit::new();
+
+/// 调度事件的类型
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum TraceEvent {
+    /// 任务开始在CPU上运行
+    SwitchIn = 1,
+    /// 任务停止在CPU上运行
+    SwitchOut = 2,
+    /// 任务进入阻塞
+    Block = 3,
+    /// 任务被唤醒，参数为是否放入全局调度器
+    Wake = 4,
+    /// 任务被创建并加入调度器
+    Spawn = 5,
+    /// 任务退出或被终止
+    Exit = 6,
+    /// 任务被抢占
+    Preempt = 7,
+    /// 亲和性不允许任务在取出它的CPU上运行，任务被转移到其它CPU的收件箱（或全局调度器）
+    Migrate = 8,
+}
+
+impl TraceEvent {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => Self::SwitchIn,
+            2 => Self::SwitchOut,
+            3 => Self::Block,
+            4 => Self::Wake,
+            5 => Self::Spawn,
+            6 => Self::Exit,
+            7 => Self::Preempt,
+            8 => Self::Migrate,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::SwitchIn => "switch_in",
+            Self::SwitchOut => "switch_out",
+            Self::Block => "block",
+            Self::Wake => "wake",
+            Self::Spawn => "spawn",
+            Self::Exit => "exit",
+            Self::Preempt => "preempt",
+            Self::Migrate => "migrate",
+        }
+    }
+}
+
+/// 缓冲区中的一项
+/// `seq`为奇数代表正在写入，为偶数代表写入完成，其值可用于判断该项是否已被之后的事件覆盖
+struct TraceSlot {
+    seq: AtomicU64,
+    timestamp: AtomicU64,
+    task_id: AtomicU64,
+    /// 低8位为事件类型，其余位为参数
+    info: AtomicU64,
+}
+
+struct TraceBuffer {
+    /// 下一个事件的序号
+    head: AtomicU64,
+    slots: Vec<TraceSlot>,
+}
+
+impl TraceBuffer {
+    fn new() -> Self {
+        Self {
+            head: AtomicU64::new(0),
+            slots: (0 .. TRACE_BUFFER_LEN).map(|_| TraceSlot {
+                seq: AtomicU64::new(0),
+                timestamp: AtomicU64::new(0),
+                task_id: AtomicU64::new(0),
+                info: AtomicU64::new(0),
+            }).collect(),
+        }
+    }
+
+    /// 通过原子操作占用一项后写入，因此中断处理函数或其它CPU同时写入时不会冲突
+    fn push(&self, event: TraceEvent, task_id: u64, arg: u64) {
+        let index = self.head.fetch_add(1, Ordering::AcqRel);
+        let slot = &self.slots[index as usize % TRACE_BUFFER_LEN];
+        slot.seq.store(index * 2 + 1, Ordering::Relaxed);
+        // 使之后对数据的写入不会被重排到标记正在写入之前
+        fence(Ordering::Release);
+        slot.timestamp.store(timer::current_ticks() as u64, Ordering::Relaxed);
+        slot.task_id.store(task_id, Ordering::Relaxed);
+        slot.info.store(event as u64 | (arg << 8), Ordering::Relaxed);
+        slot.seq.store(index * 2 + 2, Ordering::Release);
+    }
+
+    /// 读取序号为`index`的事件，若该项正在写入或已被覆盖则返回None
+    fn get(&self, index: u64) -> Option<(u64, u64, TraceEvent, u64)> {
+        let slot = &self.slots[index as usize % TRACE_BUFFER_LEN];
+        if slot.seq.load(Ordering::Acquire) != index * 2 + 2 {
+            return None;
+        }
+        let timestamp = slot.timestamp.load(Ordering::Relaxed);
+        let task_id = slot.task_id.load(Ordering::Relaxed);
+        let info = slot.info.load(Ordering::Relaxed);
+        // 使之前对数据的读取不会被重排到再次读取序号之后
+        fence(Ordering::Acquire);
+        if slot.seq.load(Ordering::Relaxed) != index * 2 + 2 {
+            return None;
+        }
+        Some((timestamp, task_id, TraceEvent::from_u8(info as u8)?, info >> 8))
+    }
+
+    fn clear(&self) {
+        for slot in &self.slots {
+            slot.seq.store(0, Ordering::Release);
+        }
+    }
+}
+
+pub(crate) fn init(cpu_num: usize) {
+    TRACE_BUFFERS.init_by((0 .. cpu_num).map(|_| TraceBuffer::new()).collect());
+}
+
+/// 在id为`cpu_id`的CPU的缓冲区中记录一个事件
+#[inline]
+pub(crate) fn record(cpu_id: usize, event: TraceEvent, task_id: u64, arg: u64) {
+    if let Some(buffer) = TRACE_BUFFERS.try_get().and_then(|buffers| buffers.get(cpu_id)) {
+        buffer.push(event, task_id, arg);
+    }
+}
+
+/// 丢弃所有已记录的事件
+pub(crate) fn clear() {
+    for buffer in TRACE_BUFFERS.try_get().into_iter().flatten() {
+        buffer.clear();
+    }
+}
+
+/// 将所有CPU的缓冲区导出为Chrome trace event格式的JSON
+/// 每个CPU对应一个线程（tid），任务在CPU上的运行区间导出为持续事件，其余事件导出为瞬时事件。
+pub(crate) fn export<W: Write>(w: &mut W) -> fmt::Result {
+    w.write_str("{\"traceEvents\":[")?;
+    let mut first = true;
+    for (cpu_id, buffer) in TRACE_BUFFERS.try_get().into_iter().flatten().enumerate() {
+        let head = buffer.head.load(Ordering::Acquire);
+        let start = head.saturating_sub(TRACE_BUFFER_LEN as u64);
+        // 缓冲区可能从某个任务运行的中途开始，跳过开头不成对的结束事件
+        let mut running = false;
+        for index in start .. head {
+            let Some((timestamp, task_id, event, arg)) = buffer.get(index) else { continue };
+            let phase = match event {
+                TraceEvent::SwitchIn => { running = true; "B" },
+                TraceEvent::SwitchOut if running => { running = false; "E" },
+                TraceEvent::SwitchOut => continue,
+                _ => "i",
+            };
+            if !first {
+                w.write_char(',')?;
+            }
+            first = false;
+            let nanos = timer::ticks_to_duration(timestamp).as_nanos();
+            // 运行区间以任务命名，使Perfetto的时间线上直接显示运行的任务
+            match event {
+                TraceEvent::SwitchIn | TraceEvent::SwitchOut => write!(w, "{{\"name\":\"task {}\"", task_id)?,
+                _ => write!(w, "{{\"name\":\"{}\"", event.name())?,
+            }
+            write!(w, ",\"cat\":\"sched\",\"ph\":\"{}\",\"ts\":{}.{:03},\"pid\":0,\"tid\":{}",
+                phase, nanos / 1000, nanos % 1000, cpu_id)?;
+            if phase == "i" {
+                w.write_str(",\"s\":\"t\"")?;
+            }
+            write!(w, ",\"args\":{{\"task\":{},\"event\":\"{}\",\"arg\":{}}}}}", task_id, event.name(), arg)?;
+        }
+    }
+    w.write_str("]}\n")
+}
@@ -15,6 +15,22 @@ impl<T> BlockQueue<T> {
         self.queue.push_back(task)
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// 从队列中移除指定的任务（按指针比较），用于任务因超时等原因主动离开队列的情况
+    pub fn remove(&mut self, task: &Arc<T>) -> Option<Arc<T>> {
+        let index = self.queue.iter().position(|queued_task| Arc::ptr_eq(queued_task, task))?;
+        self.queue.remove(index)
+    }
+
+    /// 仅保留满足条件的任务，用于清除队列中已经失效的任务
+    pub fn retain<F>(&mut self, cond: F)
+    where F: FnMut(&Arc<T>) -> bool {
+        self.queue.retain(cond)
+    }
+
     // wake_action的返回值：true代表中止遍历，false代表继续遍历。
     fn wake_raw_with_cond<F, G>(&mut self, cond: F, mut wake_action: G)
     where F: Fn(&T) -> bool, G: FnMut(Arc<T>) -> bool {
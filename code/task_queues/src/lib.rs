@@ -1,7 +1,9 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 extern crate alloc;
 
 pub mod current;
 pub mod block_queue;
-pub mod scheduler;
\ No newline at end of file
+pub mod scheduler;
+#[cfg(any(feature = "moic", feature = "moic_soft"))]
+pub mod moic;
\ No newline at end of file
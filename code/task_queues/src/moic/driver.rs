@@ -0,0 +1,212 @@
+//! MOIC硬件驱动
+//! MOIC由多个端口（port）组成，每个端口是一组独立的寄存器，拥有各自的当前上下文与就绪队列；每个`MmioMoic`独占一个端口。
+//! 写入操作寄存器发起操作；带有多个参数的操作先写入参数寄存器；操作的结果从结果寄存器读出。
+//! 注意：下列寄存器的偏移与编码是暂定的，尚未与 [moic_driver](https://github.com/ATS-INTC/moic_driver) 中真实硬件的寄存器布局对齐，
+//! 目前只由`moic_mock`的模拟设备实现；对接硬件前需按照硬件的布局修改这些常量。
+//! `TaskId`的低位用于传递优先级，上下文中的`None`以0表示。
+
+use core::{ptr::{read_volatile, write_volatile}, sync::atomic::{AtomicUsize, Ordering}};
+
+use super::{MoicContext, TaskId, MOIC_PRIO_NUM};
+
+/// 写：`TaskId | 优先级`，将任务加入当前上下文的就绪队列。结果为是否加入
+pub const MOIC_ADD: usize = 0x00;
+/// 读：从当前上下文的就绪队列中取出的任务，0代表没有任务
+pub const MOIC_FETCH: usize = 0x08;
+/// 写：`TaskId`，从就绪队列中移除任务。结果为是否移除
+pub const MOIC_REMOVE: usize = 0x10;
+/// 读：上一次操作的结果
+pub const MOIC_RESULT: usize = 0x18;
+/// 读：当前上下文的就绪队列中最高的优先级，没有任务时为`MOIC_PRIO_NUM`
+pub const MOIC_HIGHEST_PRIORITY: usize = 0x20;
+/// 写：切换当前的hypervisor、os、process
+pub const MOIC_SWITCH_HYPERVISOR: usize = 0x28;
+pub const MOIC_SWITCH_OS: usize = 0x30;
+pub const MOIC_SWITCH_PROCESS: usize = 0x38;
+/// 参数寄存器：上下文（hypervisor、os、process），或中断号（只使用第一个）
+pub const MOIC_ARG: [usize; 3] = [0x40, 0x48, 0x50];
+/// 写：`TaskId | 优先级`，中断号位于参数寄存器。结果为被替换的任务
+pub const MOIC_REGISTER_EXT_INTR: usize = 0x58;
+/// 写：中断号。结果为被移除的任务
+pub const MOIC_UNREGISTER_EXT_INTR: usize = 0x60;
+/// 写：任意值，收方上下文位于参数寄存器
+pub const MOIC_REGISTER_SENDER: usize = 0x68;
+/// 写：`TaskId | 优先级`，发方上下文位于参数寄存器。结果为被替换的任务
+pub const MOIC_REGISTER_RECEIVER: usize = 0x70;
+/// 写：任意值，发方上下文位于参数寄存器。结果为被移除的任务
+pub const MOIC_UNREGISTER_RECEIVER: usize = 0x78;
+/// 写：任意值，收方上下文位于参数寄存器。结果为被加入就绪队列的任务
+pub const MOIC_SEND_INTR: usize = 0x80;
+/// 写：`TaskId | 优先级`，修改就绪队列中的任务的优先级，任务留在其所在的上下文中。结果为是否修改
+pub const MOIC_SET_PRIORITY: usize = 0x88;
+
+/// 相邻端口的寄存器之间的距离
+pub const MOIC_PORT_STRIDE: usize = 0x1000;
+/// 端口数量
+pub const MOIC_PORT_NUM: usize = 64;
+
+pub(super) const PRIO_MASK: usize = MOIC_PRIO_NUM - 1;
+const _: () = assert!(MOIC_PRIO_NUM.is_power_of_two());
+
+/// 由使用该模块的系统实现，提供MOIC的位置
+#[crate_interface::def_interface]
+pub trait MoicIf {
+    /// MOIC的MMIO基地址（需已映射，可直接访问），即0号端口的寄存器的地址
+    fn moic_base() -> usize;
+}
+
+/// 一个端口的寄存器的访问方式
+pub trait MoicRegs {
+    /// 获取编号为`port`的端口
+    fn port(port: usize) -> Self;
+    fn read(&self, offset: usize) -> usize;
+    fn write(&self, offset: usize, value: usize);
+}
+
+/// 通过MMIO访问的端口
+pub struct MmioRegs {
+    base: usize,
+}
+
+impl MoicRegs for MmioRegs {
+    fn port(port: usize) -> Self {
+        let moic_base: usize = crate_interface::call_interface!(MoicIf::moic_base());
+        Self {
+            base: moic_base + port * MOIC_PORT_STRIDE,
+        }
+    }
+
+    fn read(&self, offset: usize) -> usize {
+        unsafe { read_volatile((self.base + offset) as *const usize) }
+    }
+
+    fn write(&self, offset: usize, value: usize) {
+        unsafe { write_volatile((self.base + offset) as *mut usize, value) }
+    }
+}
+
+/// 下一个未被使用的端口
+static NEXT_PORT: AtomicUsize = AtomicUsize::new(0);
+
+/// 通过寄存器操作MOIC的一个端口，提供与`SoftMoic`相同的操作
+/// 外部中断由MOIC直接接收，因此没有`ext_intr`操作。
+pub struct MmioMoic<R: MoicRegs> {
+    regs: R,
+    /// 寄存器只能写入当前上下文，因此在软件中记录一份
+    current: MoicContext,
+}
+
+impl<R: MoicRegs> MmioMoic<R> {
+    /// 占用一个新的端口
+    pub fn new() -> Self {
+        let port = NEXT_PORT.fetch_add(1, Ordering::AcqRel);
+        assert!(port < MOIC_PORT_NUM, "no free moic port");
+        Self {
+            regs: R::port(port),
+            current: MoicContext::default(),
+        }
+    }
+
+    pub fn current(&self) -> MoicContext {
+        self.current
+    }
+
+    pub fn add(&mut self, task: TaskId, priority: usize) -> bool {
+        self.regs.write(MOIC_ADD, encode(task, priority));
+        self.regs.read(MOIC_RESULT) != 0
+    }
+
+    pub fn fetch(&mut self) -> Option<TaskId> {
+        decode(self.regs.read(MOIC_FETCH))
+    }
+
+    pub fn remove(&mut self, task: TaskId) -> bool {
+        self.regs.write(MOIC_REMOVE, task.as_raw());
+        self.regs.read(MOIC_RESULT) != 0
+    }
+
+    pub fn set_priority(&mut self, task: TaskId, priority: usize) -> bool {
+        self.regs.write(MOIC_SET_PRIORITY, encode(task, priority));
+        self.regs.read(MOIC_RESULT) != 0
+    }
+
+    pub fn highest_priority(&self) -> usize {
+        self.regs.read(MOIC_HIGHEST_PRIORITY)
+    }
+
+    pub fn switch_hypervisor(&mut self, hypervisor: Option<TaskId>) {
+        self.regs.write(MOIC_SWITCH_HYPERVISOR, encode_opt(hypervisor));
+        self.current = MoicContext { hypervisor, os: None, process: None };
+    }
+
+    pub fn switch_os(&mut self, os: Option<TaskId>) {
+        self.regs.write(MOIC_SWITCH_OS, encode_opt(os));
+        self.current.os = os;
+        self.current.process = None;
+    }
+
+    pub fn switch_process(&mut self, process: Option<TaskId>) {
+        self.regs.write(MOIC_SWITCH_PROCESS, encode_opt(process));
+        self.current.process = process;
+    }
+
+    pub fn register_ext_intr_handler(&mut self, irq: usize, task: TaskId, priority: usize) -> Option<TaskId> {
+        self.regs.write(MOIC_ARG[0], irq);
+        self.regs.write(MOIC_REGISTER_EXT_INTR, encode(task, priority));
+        decode(self.regs.read(MOIC_RESULT))
+    }
+
+    pub fn unregister_ext_intr_handler(&mut self, irq: usize) -> Option<TaskId> {
+        self.regs.write(MOIC_UNREGISTER_EXT_INTR, irq);
+        decode(self.regs.read(MOIC_RESULT))
+    }
+
+    pub fn register_sender(&mut self, receiver: MoicContext) {
+        self.write_context(receiver);
+        self.regs.write(MOIC_REGISTER_SENDER, 0);
+    }
+
+    pub fn register_receiver(&mut self, sender: MoicContext, task: TaskId, priority: usize) -> Option<TaskId> {
+        self.write_context(sender);
+        self.regs.write(MOIC_REGISTER_RECEIVER, encode(task, priority));
+        decode(self.regs.read(MOIC_RESULT))
+    }
+
+    pub fn unregister_receiver(&mut self, sender: MoicContext) -> Option<TaskId> {
+        self.write_context(sender);
+        self.regs.write(MOIC_UNREGISTER_RECEIVER, 0);
+        decode(self.regs.read(MOIC_RESULT))
+    }
+
+    pub fn send_intr(&mut self, receiver: MoicContext) -> Option<TaskId> {
+        self.write_context(receiver);
+        self.regs.write(MOIC_SEND_INTR, 0);
+        decode(self.regs.read(MOIC_RESULT))
+    }
+
+    fn write_context(&self, context: MoicContext) {
+        self.regs.write(MOIC_ARG[0], encode_opt(context.hypervisor));
+        self.regs.write(MOIC_ARG[1], encode_opt(context.os));
+        self.regs.write(MOIC_ARG[2], encode_opt(context.process));
+    }
+}
+
+impl<R: MoicRegs> Default for MmioMoic<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn encode(task: TaskId, priority: usize) -> usize {
+    assert!(task.as_raw() & PRIO_MASK == 0 && priority < MOIC_PRIO_NUM);
+    task.as_raw() | priority
+}
+
+fn encode_opt(task: Option<TaskId>) -> usize {
+    task.map_or(0, |task| task.as_raw())
+}
+
+/// 寄存器中的`TaskId`，0代表None
+pub(super) fn decode(raw: usize) -> Option<TaskId> {
+    (raw != 0).then_some(TaskId::from_raw(raw & !PRIO_MASK))
+}
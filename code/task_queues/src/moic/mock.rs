@@ -0,0 +1,198 @@
+//! 模拟的MOIC设备，用于在没有MOIC硬件的平台（包括Linux用户态）上测试驱动
+//! 每个端口的寄存器访问被解码为对一个`SoftMoic`的操作，寄存器的含义见`driver`模块。
+//! 外部中断由`mock_ext_intr`模拟；与`SoftMoic`相同，IPC只能在同一端口的上下文之间发送。
+
+use alloc::collections::btree_map::BTreeMap;
+use spinlock::SpinRaw;
+
+use super::{driver::*, MoicContext, SoftMoic, TaskId};
+
+/// 一个端口的状态
+struct MockPort {
+    moic: SoftMoic,
+    args: [usize; 3],
+    result: usize,
+}
+
+impl MockPort {
+    fn context(&self) -> MoicContext {
+        MoicContext {
+            hypervisor: decode(self.args[0]),
+            os: decode(self.args[1]),
+            process: decode(self.args[2]),
+        }
+    }
+}
+
+static PORTS: SpinRaw<BTreeMap<usize, MockPort>> = SpinRaw::new(BTreeMap::new());
+
+/// 模拟的端口的寄存器
+pub struct MockRegs {
+    port: usize,
+}
+
+impl MockRegs {
+    fn with_port<F, T>(&self, f: F) -> T
+    where F: FnOnce(&mut MockPort) -> T {
+        f(PORTS.lock().get_mut(&self.port).unwrap())
+    }
+}
+
+fn encode_opt(task: Option<TaskId>) -> usize {
+    task.map_or(0, |task| task.as_raw())
+}
+
+fn decode_with_priority(raw: usize) -> (TaskId, usize) {
+    (TaskId::from_raw(raw & !PRIO_MASK), raw & PRIO_MASK)
+}
+
+impl MoicRegs for MockRegs {
+    fn port(port: usize) -> Self {
+        PORTS.lock().insert(port, MockPort {
+            moic: SoftMoic::new(),
+            args: [0; 3],
+            result: 0,
+        });
+        Self { port }
+    }
+
+    fn read(&self, offset: usize) -> usize {
+        self.with_port(|port| match offset {
+            MOIC_FETCH => encode_opt(port.moic.fetch()),
+            MOIC_RESULT => port.result,
+            MOIC_HIGHEST_PRIORITY => port.moic.highest_priority(),
+            _ => match MOIC_ARG.iter().position(|arg| *arg == offset) {
+                Some(index) => port.args[index],
+                None => panic!("invalid moic register {:#x}", offset),
+            },
+        })
+    }
+
+    fn write(&self, offset: usize, value: usize) {
+        self.with_port(|port| match offset {
+            MOIC_ADD => {
+                let (task, priority) = decode_with_priority(value);
+                port.result = port.moic.add(task, priority) as usize;
+            }
+            MOIC_REMOVE => port.result = port.moic.remove(TaskId::from_raw(value)) as usize,
+            MOIC_SET_PRIORITY => {
+                let (task, priority) = decode_with_priority(value);
+                port.result = port.moic.set_priority(task, priority) as usize;
+            }
+            MOIC_SWITCH_HYPERVISOR => port.moic.switch_hypervisor(decode(value)),
+            MOIC_SWITCH_OS => port.moic.switch_os(decode(value)),
+            MOIC_SWITCH_PROCESS => port.moic.switch_process(decode(value)),
+            MOIC_REGISTER_EXT_INTR => {
+                let (task, priority) = decode_with_priority(value);
+                port.result = encode_opt(port.moic.register_ext_intr_handler(port.args[0], task, priority));
+            }
+            MOIC_UNREGISTER_EXT_INTR => port.result = encode_opt(port.moic.unregister_ext_intr_handler(value)),
+            MOIC_REGISTER_SENDER => {
+                let receiver = port.context();
+                port.moic.register_sender(receiver);
+            }
+            MOIC_REGISTER_RECEIVER => {
+                let (task, priority) = decode_with_priority(value);
+                let sender = port.context();
+                port.result = encode_opt(port.moic.register_receiver(sender, task, priority));
+            }
+            MOIC_UNREGISTER_RECEIVER => {
+                let sender = port.context();
+                port.result = encode_opt(port.moic.unregister_receiver(sender));
+            }
+            MOIC_SEND_INTR => {
+                let receiver = port.context();
+                port.result = encode_opt(port.moic.send_intr(receiver));
+            }
+            _ => match MOIC_ARG.iter().position(|arg| *arg == offset) {
+                Some(index) => port.args[index] = value,
+                None => panic!("invalid moic register {:#x}", offset),
+            },
+        })
+    }
+}
+
+/// 模拟外部中断`irq`的到来，将注册的处理任务加入就绪队列
+/// 返回值代表是否有任务被加入就绪队列
+pub fn mock_ext_intr(irq: usize) -> bool {
+    PORTS.lock().values_mut().fold(false, |woken, port| port.moic.ext_intr(irq).is_some() || woken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moic::MOIC_PRIO_NUM;
+
+    fn task(n: usize) -> TaskId {
+        TaskId::from_raw(n * MOIC_PRIO_NUM)
+    }
+
+    fn os(n: usize) -> MoicContext {
+        MoicContext { hypervisor: None, os: Some(task(n)), process: None }
+    }
+
+    #[test]
+    fn add_fetch_remove() {
+        let mut moic = MmioMoic::<MockRegs>::new();
+        assert!(moic.add(task(1), 3));
+        assert!(!moic.add(task(1), 3));
+        assert!(moic.add(task(2), 1));
+        assert!(moic.add(task(3), 5));
+        assert_eq!(moic.highest_priority(), 1);
+        assert!(moic.remove(task(2)));
+        assert!(!moic.remove(task(2)));
+        assert!(moic.set_priority(task(3), 0));
+        assert_eq!(moic.fetch(), Some(task(3)));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        assert_eq!(moic.fetch(), None);
+        assert_eq!(moic.highest_priority(), MOIC_PRIO_NUM);
+    }
+
+    #[test]
+    fn ports_are_isolated() {
+        let mut moic1 = MmioMoic::<MockRegs>::new();
+        let mut moic2 = MmioMoic::<MockRegs>::new();
+        assert!(moic1.add(task(1), 3));
+        assert_eq!(moic2.fetch(), None);
+        assert!(moic2.add(task(1), 3));
+        assert_eq!(moic1.fetch(), Some(task(1)));
+        assert_eq!(moic2.fetch(), Some(task(1)));
+    }
+
+    #[test]
+    fn ipc() {
+        let mut moic = MmioMoic::<MockRegs>::new();
+        moic.switch_os(Some(task(20)));
+        assert_eq!(moic.current(), os(20));
+        assert_eq!(moic.register_receiver(os(10), task(1), 1), None);
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.send_intr(os(20)), None);
+        moic.register_sender(os(20));
+        assert_eq!(moic.send_intr(os(20)), Some(task(1)));
+        // 任务被加入收方的上下文
+        assert_eq!(moic.fetch(), None);
+        moic.switch_os(Some(task(20)));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        assert_eq!(moic.unregister_receiver(os(10)), Some(task(1)));
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.send_intr(os(20)), None);
+    }
+
+    #[test]
+    fn ext_intr() {
+        // 模拟的外部中断会发往所有端口，因此使用其它测试中不会用到的中断号
+        const IRQ: usize = 0x1001;
+        let mut moic = MmioMoic::<MockRegs>::new();
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.register_ext_intr_handler(IRQ, task(1), 2), None);
+        moic.switch_hypervisor(None);
+        assert!(!mock_ext_intr(IRQ + 1));
+        assert!(mock_ext_intr(IRQ));
+        assert!(!mock_ext_intr(IRQ));
+        assert_eq!(moic.fetch(), None);
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        assert_eq!(moic.unregister_ext_intr_handler(IRQ), Some(task(1)));
+        assert!(!mock_ext_intr(IRQ));
+    }
+}
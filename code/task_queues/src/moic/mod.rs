@@ -0,0 +1,359 @@
+//! MOIC调度器
+//! 以 [MOIC](https://github.com/ATS-INTC/moic) 实现`BaseScheduler`：启用`moic` feature时通过MMIO使用MOIC硬件（启用`moic_mock`时使用模拟的设备），
+//! 启用`moic_soft` feature时使用软件模拟的MOIC（`SoftMoic`）。
+//! 任务在MOIC中以`TaskId`表示，`TaskId`为任务的`Arc`指针；MOIC不持有任务的引用，由调度器持有就绪队列中以及注册到MOIC中的任务。
+
+mod soft;
+#[cfg(feature = "moic")]
+mod driver;
+#[cfg(feature = "moic_mock")]
+mod mock;
+
+use core::{ops::Deref, sync::atomic::{AtomicUsize, Ordering}};
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use scheduler::BaseScheduler;
+
+pub use soft::SoftMoic;
+#[cfg(feature = "moic")]
+pub use driver::*;
+#[cfg(feature = "moic_mock")]
+pub use mock::{mock_ext_intr, MockRegs};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "moic_mock")] {
+        type Moic = MmioMoic<MockRegs>;
+    } else if #[cfg(feature = "moic")] {
+        type Moic = MmioMoic<MmioRegs>;
+    } else {
+        type Moic = SoftMoic;
+    }
+}
+
+/// MOIC支持的优先级数量，数值越小优先级越高
+pub const MOIC_PRIO_NUM: usize = 8;
+/// 任务创建时的默认优先级
+pub const MOIC_DEFAULT_PRIO: usize = MOIC_PRIO_NUM / 2;
+
+/// MOIC中任务的标识
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TaskId(usize);
+
+impl TaskId {
+    pub const fn from_raw(raw: usize) -> Self {
+        Self(raw)
+    }
+
+    pub const fn as_raw(&self) -> usize {
+        self.0
+    }
+}
+
+/// MOIC的上下文：当前的hypervisor、os和process，每一级都以其对应的任务的`TaskId`表示，为None代表该级不存在
+/// 每个上下文拥有自己的就绪队列
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct MoicContext {
+    pub hypervisor: Option<TaskId>,
+    pub os: Option<TaskId>,
+    pub process: Option<TaskId>,
+}
+
+/// 在MOIC调度器中使用的任务
+pub struct MoicTask<T> {
+    inner: T,
+    priority: AtomicUsize,
+}
+
+impl<T> MoicTask<T> {
+    pub const fn new(inner: T) -> Self {
+        Self {
+            inner,
+            priority: AtomicUsize::new(MOIC_DEFAULT_PRIO),
+        }
+    }
+
+    pub const fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// 不命名为`priority`，避免覆盖`inner`的同名方法
+    pub fn moic_priority(&self) -> usize {
+        self.priority.load(Ordering::Acquire)
+    }
+
+    fn task_id(self: &Arc<Self>) -> TaskId {
+        TaskId(Arc::as_ptr(self) as usize)
+    }
+}
+
+impl<T> Deref for MoicTask<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+/// 以MOIC实现的调度器
+pub struct MoicScheduler<T> {
+    moic: Moic,
+    /// 由`add_task`显式加入就绪队列、还未被取出的任务，与中断或IPC加入的就绪队列项分开记录
+    /// 包括加入时已经被中断或IPC加入就绪队列的注册任务，使其在注册撤销后仍留在就绪队列中
+    queued: BTreeMap<TaskId, Arc<MoicTask<T>>>,
+    /// 注册到MOIC中的外部中断处理任务与IPC收方任务，及其注册次数
+    /// 这些任务可能由MOIC直接加入就绪队列，因此需要保证其在注册期间不被释放
+    registered: BTreeMap<TaskId, (Arc<MoicTask<T>>, usize)>,
+}
+
+impl<T> MoicScheduler<T> {
+    pub fn new() -> Self {
+        Self {
+            moic: Moic::new(),
+            queued: BTreeMap::new(),
+            registered: BTreeMap::new(),
+        }
+    }
+
+    pub fn current(&self) -> MoicContext {
+        self.moic.current()
+    }
+
+    pub fn switch_hypervisor(&mut self, hypervisor: Option<TaskId>) {
+        self.moic.switch_hypervisor(hypervisor)
+    }
+
+    pub fn switch_os(&mut self, os: Option<TaskId>) {
+        self.moic.switch_os(os)
+    }
+
+    pub fn switch_process(&mut self, process: Option<TaskId>) {
+        self.moic.switch_process(process)
+    }
+
+    /// 在当前上下文中注册外部中断`irq`的处理任务，注册时使用任务当前的优先级
+    pub fn register_ext_intr_handler(&mut self, irq: usize, task: Arc<MoicTask<T>>) {
+        let task_id = task.task_id();
+        let replaced = self.moic.register_ext_intr_handler(irq, task_id, task.moic_priority());
+        self.register(task_id, task);
+        if let Some(replaced) = replaced {
+            self.unregister(replaced);
+        }
+    }
+
+    pub fn unregister_ext_intr_handler(&mut self, irq: usize) {
+        if let Some(task_id) = self.moic.unregister_ext_intr_handler(irq) {
+            self.unregister(task_id);
+        }
+    }
+
+    /// 外部中断到来，将处理任务加入其所属上下文的就绪队列
+    /// 软件模拟的MOIC不能直接接收外部中断，需要在中断处理函数中调用
+    /// 返回值代表是否有任务被加入就绪队列
+    #[cfg(not(feature = "moic"))]
+    pub fn ext_intr(&mut self, irq: usize) -> bool {
+        self.moic.ext_intr(irq).is_some()
+    }
+
+    /// 允许当前上下文向`receiver`上下文发送IPC
+    pub fn register_sender(&mut self, receiver: MoicContext) {
+        self.moic.register_sender(receiver)
+    }
+
+    /// 在当前上下文中注册接收`sender`上下文的IPC的任务，注册时使用任务当前的优先级
+    pub fn register_receiver(&mut self, sender: MoicContext, task: Arc<MoicTask<T>>) {
+        let task_id = task.task_id();
+        let replaced = self.moic.register_receiver(sender, task_id, task.moic_priority());
+        self.register(task_id, task);
+        if let Some(replaced) = replaced {
+            self.unregister(replaced);
+        }
+    }
+
+    pub fn unregister_receiver(&mut self, sender: MoicContext) {
+        if let Some(task_id) = self.moic.unregister_receiver(sender) {
+            self.unregister(task_id);
+        }
+    }
+
+    /// 从当前上下文向`receiver`上下文发送IPC
+    /// 返回值代表是否有任务被加入就绪队列
+    pub fn send_intr(&mut self, receiver: MoicContext) -> bool {
+        self.moic.send_intr(receiver).is_some()
+    }
+
+    /// 同一任务可以注册多次，注册记录带有计数
+    fn register(&mut self, task_id: TaskId, task: Arc<MoicTask<T>>) {
+        self.registered.entry(task_id).or_insert((task, 0)).1 += 1;
+    }
+
+    /// 任务的注册全部撤销后，其由中断或IPC产生、还未被取出的就绪队列项也随之失效
+    fn unregister(&mut self, task_id: TaskId) {
+        let count = &mut self.registered.get_mut(&task_id).unwrap().1;
+        *count -= 1;
+        if *count == 0 {
+            self.registered.remove(&task_id);
+            if !self.queued.contains_key(&task_id) {
+                self.moic.remove(task_id);
+            }
+        }
+    }
+
+    /// 取得从MOIC中取出的任务
+    fn take(&mut self, task_id: TaskId) -> Arc<MoicTask<T>> {
+        match self.queued.remove(&task_id) {
+            Some(task) => task,
+            None => self.registered.get(&task_id).expect("unknown task fetched from moic").0.clone(),
+        }
+    }
+}
+
+impl<T> Default for MoicScheduler<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> BaseScheduler for MoicScheduler<T> {
+    type SchedItem = Arc<MoicTask<T>>;
+
+    fn init(&mut self) { }
+
+    /// 任务已经在就绪队列中（例如已被中断或IPC加入）时，不重复加入，但仍记录为显式加入
+    fn add_task(&mut self, task: Self::SchedItem) {
+        let task_id = task.task_id();
+        self.moic.add(task_id, task.moic_priority());
+        self.queued.insert(task_id, task);
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        let task_id = task.task_id();
+        self.moic.remove(task_id).then(|| self.take(task_id))
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        let task_id = self.moic.fetch()?;
+        Some(self.take(task_id))
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, _preempt: bool) {
+        self.add_task(prev)
+    }
+
+    /// MOIC不使用时间片
+    fn task_tick(&mut self, _current: &Self::SchedItem) -> bool {
+        false
+    }
+
+    /// 就绪队列中有优先级高于当前任务的任务时，需要重调度
+    fn scheduler_tick(&mut self, current: &Self::SchedItem) -> bool {
+        self.moic.highest_priority() < current.moic_priority()
+    }
+
+    /// 不改变任务注册到MOIC中时使用的优先级
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        if prio < 0 || prio as usize >= MOIC_PRIO_NUM {
+            return false;
+        }
+        task.priority.store(prio as usize, Ordering::Release);
+        // 位于就绪队列中的任务，需要在其所在的上下文中移动到新优先级的队列
+        self.moic.set_priority(task.task_id(), prio as usize);
+        true
+    }
+
+    fn highest_priority(&self) -> isize {
+        self.moic.highest_priority() as isize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_task(n: usize) -> Arc<MoicTask<usize>> {
+        Arc::new(MoicTask::new(n))
+    }
+
+    /// 在当前上下文中注册接收自身IPC的任务，之后可用`send_intr(context)`模拟IPC将其加入就绪队列
+    fn register_self_receiver(scheduler: &mut MoicScheduler<usize>, task: &Arc<MoicTask<usize>>) -> MoicContext {
+        let context = scheduler.current();
+        scheduler.register_sender(context);
+        scheduler.register_receiver(context, task.clone());
+        context
+    }
+
+    #[test]
+    fn add_remove_pick() {
+        let mut scheduler = MoicScheduler::new();
+        let task = new_task(1);
+        scheduler.add_task(task.clone());
+        scheduler.add_task(task.clone());
+        assert!(Arc::ptr_eq(&scheduler.remove_task(&task).unwrap(), &task));
+        assert!(scheduler.remove_task(&task).is_none());
+        assert!(scheduler.pick_next_task().is_none());
+        assert!(scheduler.queued.is_empty());
+        assert_eq!(Arc::strong_count(&task), 1);
+    }
+
+    #[test]
+    fn registered_task_is_kept_alive() {
+        let mut scheduler = MoicScheduler::new();
+        let task = new_task(1);
+        let context = register_self_receiver(&mut scheduler, &task);
+        let weak = Arc::downgrade(&task);
+        drop(task);
+        assert!(scheduler.send_intr(context));
+        let task = scheduler.pick_next_task().unwrap();
+        assert!(Arc::ptr_eq(&task, &weak.upgrade().unwrap()));
+        // 被MOIC取出的注册任务不会从注册记录中移除
+        assert!(scheduler.registered.contains_key(&task.task_id()));
+        assert!(scheduler.queued.is_empty());
+    }
+
+    #[test]
+    fn unregister_drops_moic_entry() {
+        let mut scheduler = MoicScheduler::new();
+        let task = new_task(1);
+        let context = register_self_receiver(&mut scheduler, &task);
+        assert!(scheduler.send_intr(context));
+        scheduler.unregister_receiver(context);
+        assert!(scheduler.registered.is_empty());
+        assert!(scheduler.pick_next_task().is_none());
+        assert_eq!(Arc::strong_count(&task), 1);
+    }
+
+    #[test]
+    fn explicit_add_survives_unregister() {
+        let mut scheduler = MoicScheduler::new();
+        let task = new_task(1);
+        let context = register_self_receiver(&mut scheduler, &task);
+        assert!(scheduler.send_intr(context));
+        // 任务已经被IPC加入就绪队列，之后又被显式加入
+        scheduler.add_task(task.clone());
+        scheduler.unregister_receiver(context);
+        assert!(scheduler.registered.is_empty());
+        assert!(Arc::ptr_eq(&scheduler.pick_next_task().unwrap(), &task));
+        assert!(scheduler.pick_next_task().is_none());
+        assert!(scheduler.queued.is_empty());
+    }
+
+    #[test]
+    fn registrations_are_counted() {
+        let mut scheduler = MoicScheduler::new();
+        let task = new_task(1);
+        let context = register_self_receiver(&mut scheduler, &task);
+        let other = MoicContext { hypervisor: None, os: Some(TaskId::from_raw(MOIC_PRIO_NUM)), process: None };
+        scheduler.register_receiver(other, task.clone());
+        assert_eq!(scheduler.registered[&task.task_id()].1, 2);
+        assert!(scheduler.send_intr(context));
+        scheduler.unregister_receiver(other);
+        assert_eq!(scheduler.registered[&task.task_id()].1, 1);
+        assert!(Arc::ptr_eq(&scheduler.pick_next_task().unwrap(), &task));
+        // 替换注册的任务时，撤销被替换任务的注册
+        let replacement = new_task(2);
+        scheduler.register_receiver(context, replacement.clone());
+        assert!(!scheduler.registered.contains_key(&task.task_id()));
+        assert_eq!(Arc::strong_count(&task), 1);
+        scheduler.unregister_receiver(context);
+        assert!(scheduler.registered.is_empty());
+    }
+}
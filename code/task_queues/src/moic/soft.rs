@@ -0,0 +1,280 @@
+use alloc::collections::{btree_map::BTreeMap, btree_set::BTreeSet, VecDeque};
+
+use super::{MoicContext, TaskId, MOIC_PRIO_NUM};
+
+/// 一个上下文（进程）的就绪队列，按优先级分为多个先进先出队列
+struct ReadyQueue {
+    queues: [VecDeque<TaskId>; MOIC_PRIO_NUM],
+}
+
+impl ReadyQueue {
+    fn new() -> Self {
+        Self {
+            queues: core::array::from_fn(|_| VecDeque::new()),
+        }
+    }
+
+    fn highest_priority(&self) -> usize {
+        self.queues.iter().position(|queue| !queue.is_empty()).unwrap_or(MOIC_PRIO_NUM)
+    }
+}
+
+/// 注册的IPC收方
+#[derive(Clone, Copy)]
+struct Receiver {
+    task: TaskId,
+    priority: usize,
+}
+
+/// 用软件模拟的MOIC
+/// 提供与MOIC硬件相同的操作：按`TaskId`加入、取出、移除任务；切换当前的hypervisor、os、process；注册外部中断处理任务与IPC的发方、收方。
+/// 加入、取出操作针对当前上下文的就绪队列；外部中断与IPC会将注册的任务加入其所属上下文的就绪队列。
+pub struct SoftMoic {
+    current: MoicContext,
+    ready_queues: BTreeMap<MoicContext, ReadyQueue>,
+    /// 位于就绪队列中的任务，及其所在的上下文与优先级
+    queued: BTreeMap<TaskId, (MoicContext, usize)>,
+    /// 外部中断号 -> 处理任务、其所属的上下文与优先级
+    ext_intr_handlers: BTreeMap<usize, (TaskId, MoicContext, usize)>,
+    /// (发方上下文, 收方上下文)，代表允许发方向收方发送IPC
+    senders: BTreeSet<(MoicContext, MoicContext)>,
+    /// (收方上下文, 发方上下文) -> 收方中接收该发方IPC的任务
+    receivers: BTreeMap<(MoicContext, MoicContext), Receiver>,
+}
+
+impl SoftMoic {
+    pub fn new() -> Self {
+        Self {
+            current: MoicContext::default(),
+            ready_queues: BTreeMap::new(),
+            queued: BTreeMap::new(),
+            ext_intr_handlers: BTreeMap::new(),
+            senders: BTreeSet::new(),
+            receivers: BTreeMap::new(),
+        }
+    }
+
+    pub fn current(&self) -> MoicContext {
+        self.current
+    }
+
+    /// 将任务加入当前上下文的就绪队列
+    /// 任务已经在就绪队列中时返回false
+    pub fn add(&mut self, task: TaskId, priority: usize) -> bool {
+        self.add_to(self.current, task, priority)
+    }
+
+    /// 从当前上下文的就绪队列中取出优先级最高的任务
+    pub fn fetch(&mut self) -> Option<TaskId> {
+        let ready_queue = self.ready_queues.get_mut(&self.current)?;
+        let priority = ready_queue.highest_priority();
+        let task = ready_queue.queues.get_mut(priority)?.pop_front()?;
+        self.queued.remove(&task);
+        Some(task)
+    }
+
+    /// 从任务所在的就绪队列中移除任务，任务不在就绪队列中时返回false
+    pub fn remove(&mut self, task: TaskId) -> bool {
+        let Some((context, priority)) = self.queued.remove(&task) else { return false };
+        let queue = &mut self.ready_queues.get_mut(&context).unwrap().queues[priority];
+        let index = queue.iter().position(|queued_task| *queued_task == task).unwrap();
+        queue.remove(index);
+        true
+    }
+
+    /// 修改就绪队列中的任务的优先级，任务留在其所在的上下文中，并移动到新优先级的队列末尾
+    /// 任务不在就绪队列中时返回false
+    pub fn set_priority(&mut self, task: TaskId, priority: usize) -> bool {
+        let Some(&(context, _)) = self.queued.get(&task) else { return false };
+        self.remove(task);
+        self.add_to(context, task, priority)
+    }
+
+    /// 任务所在的就绪队列及优先级，不在就绪队列中时返回None
+    pub fn queued_priority(&self, task: TaskId) -> Option<usize> {
+        self.queued.get(&task).map(|(_, priority)| *priority)
+    }
+
+    /// 当前上下文的就绪队列中，优先级最高的任务的优先级；没有任务时为`MOIC_PRIO_NUM`
+    pub fn highest_priority(&self) -> usize {
+        self.ready_queues.get(&self.current).map_or(MOIC_PRIO_NUM, ReadyQueue::highest_priority)
+    }
+
+    /// 切换hypervisor，同时清除当前的os与process
+    pub fn switch_hypervisor(&mut self, hypervisor: Option<TaskId>) {
+        self.current = MoicContext { hypervisor, os: None, process: None };
+    }
+
+    /// 切换当前hypervisor下的os，同时清除当前的process
+    pub fn switch_os(&mut self, os: Option<TaskId>) {
+        self.current.os = os;
+        self.current.process = None;
+    }
+
+    /// 切换当前os下的process
+    pub fn switch_process(&mut self, process: Option<TaskId>) {
+        self.current.process = process;
+    }
+
+    /// 在当前上下文中注册外部中断处理任务，替换之前注册的任务
+    pub fn register_ext_intr_handler(&mut self, irq: usize, task: TaskId, priority: usize) -> Option<TaskId> {
+        self.ext_intr_handlers.insert(irq, (task, self.current, priority)).map(|(task, _, _)| task)
+    }
+
+    pub fn unregister_ext_intr_handler(&mut self, irq: usize) -> Option<TaskId> {
+        self.ext_intr_handlers.remove(&irq).map(|(task, _, _)| task)
+    }
+
+    /// 外部中断到来，将处理任务加入其所属上下文的就绪队列
+    /// 返回被加入就绪队列的任务，没有注册处理任务或任务已经在就绪队列中时返回None
+    pub fn ext_intr(&mut self, irq: usize) -> Option<TaskId> {
+        let (task, context, priority) = *self.ext_intr_handlers.get(&irq)?;
+        self.add_to(context, task, priority).then_some(task)
+    }
+
+    /// 允许当前上下文向`receiver`上下文发送IPC
+    pub fn register_sender(&mut self, receiver: MoicContext) {
+        self.senders.insert((self.current, receiver));
+    }
+
+    /// 在当前上下文中注册接收`sender`上下文的IPC的任务，替换之前注册的任务
+    pub fn register_receiver(&mut self, sender: MoicContext, task: TaskId, priority: usize) -> Option<TaskId> {
+        self.receivers.insert((self.current, sender), Receiver { task, priority }).map(|receiver| receiver.task)
+    }
+
+    pub fn unregister_receiver(&mut self, sender: MoicContext) -> Option<TaskId> {
+        self.receivers.remove(&(self.current, sender)).map(|receiver| receiver.task)
+    }
+
+    /// 从当前上下文向`receiver`上下文发送IPC，将收方任务加入其所属上下文的就绪队列
+    /// 返回被加入就绪队列的任务，未注册发方与收方或任务已经在就绪队列中时返回None
+    pub fn send_intr(&mut self, receiver: MoicContext) -> Option<TaskId> {
+        if !self.senders.contains(&(self.current, receiver)) {
+            return None;
+        }
+        let Receiver { task, priority } = *self.receivers.get(&(receiver, self.current))?;
+        self.add_to(receiver, task, priority).then_some(task)
+    }
+
+    fn add_to(&mut self, context: MoicContext, task: TaskId, priority: usize) -> bool {
+        assert!(priority < MOIC_PRIO_NUM, "invalid moic priority {}", priority);
+        if self.queued.contains_key(&task) {
+            return false;
+        }
+        self.queued.insert(task, (context, priority));
+        self.ready_queues.entry(context).or_insert_with(ReadyQueue::new).queues[priority].push_back(task);
+        true
+    }
+}
+
+impl Default for SoftMoic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(n: usize) -> TaskId {
+        TaskId::from_raw(n * MOIC_PRIO_NUM)
+    }
+
+    fn os(n: usize) -> MoicContext {
+        MoicContext { hypervisor: None, os: Some(task(n)), process: None }
+    }
+
+    #[test]
+    fn fetch_by_priority() {
+        let mut moic = SoftMoic::new();
+        assert!(moic.add(task(1), 3));
+        assert!(moic.add(task(2), 1));
+        assert!(moic.add(task(3), 3));
+        assert_eq!(moic.highest_priority(), 1);
+        assert_eq!(moic.fetch(), Some(task(2)));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        assert_eq!(moic.fetch(), Some(task(3)));
+        assert_eq!(moic.fetch(), None);
+        assert_eq!(moic.highest_priority(), MOIC_PRIO_NUM);
+    }
+
+    #[test]
+    fn duplicate_add() {
+        let mut moic = SoftMoic::new();
+        assert!(moic.add(task(1), 3));
+        assert!(!moic.add(task(1), 3));
+        assert!(!moic.add(task(1), 0));
+        assert_eq!(moic.queued_priority(task(1)), Some(3));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        assert_eq!(moic.fetch(), None);
+        assert!(moic.add(task(1), 3));
+    }
+
+    #[test]
+    fn contexts_are_isolated() {
+        let mut moic = SoftMoic::new();
+        assert!(moic.add(task(1), 3));
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.current(), os(10));
+        assert_eq!(moic.fetch(), None);
+        assert!(moic.add(task(2), 3));
+        moic.switch_process(Some(task(11)));
+        assert_eq!(moic.highest_priority(), MOIC_PRIO_NUM);
+        // 切换os时清除当前的process
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.fetch(), Some(task(2)));
+        moic.switch_hypervisor(None);
+        assert_eq!(moic.current(), MoicContext::default());
+        assert_eq!(moic.fetch(), Some(task(1)));
+    }
+
+    #[test]
+    fn set_priority_keeps_context() {
+        let mut moic = SoftMoic::new();
+        assert!(moic.add(task(1), 5));
+        moic.switch_os(Some(task(10)));
+        assert!(moic.set_priority(task(1), 0));
+        assert_eq!(moic.fetch(), None);
+        moic.switch_hypervisor(None);
+        assert!(moic.add(task(2), 2));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        assert!(!moic.set_priority(task(1), 0));
+    }
+
+    #[test]
+    fn ext_intr_wakes_registered_task() {
+        let mut moic = SoftMoic::new();
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.register_ext_intr_handler(5, task(1), 2), None);
+        moic.switch_hypervisor(None);
+        assert_eq!(moic.ext_intr(6), None);
+        assert_eq!(moic.ext_intr(5), Some(task(1)));
+        // 任务已经在就绪队列中
+        assert_eq!(moic.ext_intr(5), None);
+        // 任务被加入注册时的上下文
+        assert_eq!(moic.fetch(), None);
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        assert_eq!(moic.unregister_ext_intr_handler(5), Some(task(1)));
+        assert_eq!(moic.ext_intr(5), None);
+    }
+
+    #[test]
+    fn send_intr_requires_sender() {
+        let mut moic = SoftMoic::new();
+        moic.switch_os(Some(task(20)));
+        assert_eq!(moic.register_receiver(os(10), task(1), 1), None);
+        moic.switch_os(Some(task(10)));
+        assert_eq!(moic.send_intr(os(20)), None);
+        moic.register_sender(os(20));
+        assert_eq!(moic.send_intr(os(20)), Some(task(1)));
+        moic.switch_os(Some(task(20)));
+        assert_eq!(moic.fetch(), Some(task(1)));
+        // 未被允许发送的上下文
+        moic.switch_os(Some(task(30)));
+        assert_eq!(moic.send_intr(os(20)), None);
+        moic.switch_os(Some(task(20)));
+        assert_eq!(moic.fetch(), None);
+    }
+}
@@ -3,7 +3,10 @@ use scheduler::{ CFScheduler, CFSTask, FifoScheduler, FifoTask, RRScheduler, RRT
 pub use scheduler::BaseScheduler;
 
 cfg_if::cfg_if! {
-    if #[cfg(feature = "sched_rr")] {
+    if #[cfg(any(feature = "moic", feature = "moic_soft"))] {
+        pub type AxTask<T> = crate::moic::MoicTask<T>;
+        pub type Scheduler<T> = crate::moic::MoicScheduler<T>;
+    } else if #[cfg(feature = "sched_rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub type AxTask<T> = scheduler::RRTask<T, MAX_TIME_SLICE>;
         pub type Scheduler<T> = scheduler::RRScheduler<T, MAX_TIME_SLICE>;
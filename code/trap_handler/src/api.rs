@@ -1,6 +1,12 @@
 // -----初始化-----
 
 use alloc::boxed::Box;
+#[cfg(any(feature = "plic", feature = "aia"))]
+use alloc::sync::Arc;
+#[cfg(any(feature = "plic", feature = "aia"))]
+use core::future::Future;
+#[cfg(any(feature = "plic", feature = "aia"))]
+use task_management::{SpawnError, Task, TaskBuilder};
 use riscv::register::{scause::Trap, sie, sstatus};
 use core::sync::atomic::{AtomicBool, Ordering};
 
@@ -14,6 +20,16 @@ use crate::timer::init_timer_on_main_processor;
 use crate::timer::init_timer_on_secondary_processor;
 #[cfg(feature = "timer")]
 pub use crate::timer::CurrentTimebaseFrequency;
+#[cfg(all(feature = "plic", not(feature = "aia")))]
+pub use crate::intc::plic::{PlicIf, PLIC_MAX_PRIORITY, PLIC_MAX_SOURCES};
+#[cfg(feature = "aia")]
+pub use crate::intc::aia::{AiaIf, AIA_MAX_SOURCES};
+#[cfg(any(feature = "plic", feature = "aia"))]
+use crate::intc::{init_intc_on_current_processor, init_intc_on_main_processor};
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub use crate::intc::IrqTrigger;
+#[cfg(any(feature = "plic", feature = "aia"))]
+use task_management::current_processor_id;
 
 #[cfg(feature = "smp")]
 static MAIN_PROCESSOR_INIT_FINISHED: AtomicBool = AtomicBool::new(false);
@@ -29,6 +45,8 @@ pub fn init_main_processor() {
     }
     #[cfg(feature = "timer")]
     init_timer_on_main_processor();
+    #[cfg(any(feature = "plic", feature = "aia"))]
+    init_intc_on_main_processor(current_processor_id());
     // enable_irqs();
 
     #[cfg(feature = "smp")]
@@ -50,6 +68,8 @@ pub fn init_secondary_processor() {
     }
     #[cfg(feature = "timer")]
     init_timer_on_secondary_processor();
+    #[cfg(any(feature = "plic", feature = "aia"))]
+    init_intc_on_current_processor(current_processor_id());
     // enable_irqs();
 }
 
@@ -89,9 +109,80 @@ where F: Fn() + Send + Sync + 'static {
     EXTINTR_HANDLER.insert(irq_num, Box::new(handler));
 }
 
+/// 根据中断号，注册线程化的中断处理程序：创建一个线程，在每次中断到来时执行`handler`
+/// 中断到来时只屏蔽该中断源并唤醒该线程（`to_global`为true时放入全局调度器，否则放入收到中断的CPU的调度器），
+/// 线程以`builder`设置的优先级等属性运行，`handler`中可以阻塞，返回后中断源被重新使能。
+/// 线程开始运行时注册处理程序并使能中断源，使用PLIC时需要先设置中断源的优先级。
+/// 线程被`kill`终止后，中断源被禁用，处理程序被注销。
+/// 注意：与`register_extintr_handler`注册的同一中断号的处理程序互相覆盖。
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn register_threaded_irq_handler<F>(irq_num: usize, to_global: bool, builder: TaskBuilder, handler: F) -> Result<Arc<Task>, SpawnError>
+where F: FnMut() + Send + 'static {
+    crate::threaded::register_threaded_irq_handler(irq_num, to_global, builder, handler)
+}
+
+/// 根据中断号，注册线程化的中断处理程序（协程版本）：创建一个协程，在每次中断到来时执行`handler`返回的Future
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn register_threaded_irq_handler_async<F, Fut>(irq_num: usize, to_global: bool, builder: TaskBuilder, handler: F) -> Result<Arc<Task>, SpawnError>
+where F: FnMut() -> Fut + Send + 'static, Fut: Future<Output = ()> + Send + 'static {
+    crate::threaded::register_threaded_irq_handler_async(irq_num, to_global, builder, handler)
+}
+
 /// 根据系统调用号，注册系统调用处理程序
 /// 注意：使用register_trap_handler函数注册Exception(UserEnvCall)的trap_handler会覆盖使用该函数注册的处理程序。
 pub fn register_syscall_handler<F>(sc_num: usize, handler: F)
 where F: (Fn([usize; 6]) -> usize) + Send + Sync + 'static {
     SYSCALL_HANDLER.insert(sc_num, Box::new(handler));
-}
\ No newline at end of file
+}
+
+// -----外部中断控制-----
+// 以下接口对PLIC与AIA相同
+
+/// 代表当前使用的中断控制器不支持该设置的错误
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub struct UnsupportedIrqConfigError;
+
+/// 设置中断源的优先级，优先级为0的中断源不会触发中断
+/// PLIC：优先级为1 ~ `PLIC_MAX_PRIORITY`，需要大于CPU的阈值才会触发中断；
+/// AIA：优先级由中断号决定（中断号越小优先级越高），不支持该设置，总是返回Err
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn set_irq_priority(irq_num: usize, priority: u32) -> Result<(), UnsupportedIrqConfigError> {
+    if crate::intc::set_irq_priority(irq_num, priority) { Ok(()) } else { Err(UnsupportedIrqConfigError) }
+}
+
+/// 使能中断源，之后其会被发送到亲和性允许的CPU上
+/// 使用PLIC时，使能前需要设置非0的优先级
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn enable_irq(irq_num: usize) {
+    crate::intc::set_irq_enabled(irq_num, true);
+}
+
+/// 禁用中断源
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn disable_irq(irq_num: usize) {
+    crate::intc::set_irq_enabled(irq_num, false);
+}
+
+/// 设置中断源可以被发送到的CPU，`cpu_set`为位图，第i位为1代表可以发送到id为i的CPU。默认可以发送到所有CPU
+/// PLIC：中断同时发送到多个CPU，只有一个CPU会取得并处理该中断；
+/// AIA：中断只发送到其中id最小的CPU
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn set_irq_affinity(irq_num: usize, cpu_set: u64) {
+    crate::intc::set_irq_affinity(irq_num, cpu_set);
+}
+
+/// 设置中断源的触发方式，默认为高电平触发
+/// PLIC：触发方式由平台的中断网关决定，总是返回Err；
+/// AIA：写入APLIC中该中断源的sourcecfg
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn set_irq_trigger(irq_num: usize, trigger: IrqTrigger) -> Result<(), UnsupportedIrqConfigError> {
+    if crate::intc::set_irq_trigger(irq_num, trigger) { Ok(()) } else { Err(UnsupportedIrqConfigError) }
+}
+
+/// 设置当前CPU的中断阈值，初始化时为0（接受所有中断）
+/// PLIC：只有优先级大于阈值的中断会被发送到当前CPU；
+/// AIA：阈值不为0时，只有中断号小于阈值的中断会被发送到当前CPU
+#[cfg(any(feature = "plic", feature = "aia"))]
+pub fn set_current_irq_threshold(threshold: u32) {
+    crate::intc::set_current_threshold(current_processor_id(), threshold);
+}
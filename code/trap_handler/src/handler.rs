@@ -5,6 +5,11 @@ use lazy_init::LazyInit;
 use riscv::register::{scause::{self, Exception, Interrupt, Trap}, stval};
 use spinlock::{SpinNoIrq, SpinNoIrqOnly};
 use task_management::TaskContext;
+#[cfg(any(feature = "plic", feature = "aia"))]
+use task_management::current_processor_id;
+
+#[cfg(any(feature = "plic", feature = "aia"))]
+use crate::intc;
 
 #[cfg(feature = "log")]
 use axlog::debug;
@@ -62,13 +67,15 @@ pub(crate) fn init_handler() {
         panic!("Unhandled system call!");
     })));
 
+    #[cfg(any(feature = "plic", feature = "aia"))]
     INTERRUPT_HANDLER.insert(Interrupt::SupervisorExternal.try_into().unwrap(), Box::new(|_stval, _context| {
-        let irq_num: usize = unimplemented!(); // 从plic处获取中断号
-
-        #[cfg(feature = "log")]
-        debug!("New external interrupt, irq_num: {}", irq_num);
+        // 从中断控制器处获取中断号，处理完成后通知中断控制器
+        intc::handle_irq(current_processor_id(), |irq_num| {
+            #[cfg(feature = "log")]
+            debug!("New external interrupt, irq_num: {}", irq_num);
 
-        EXTINTR_HANDLER.get_ref(irq_num)();
+            EXTINTR_HANDLER.get_ref(irq_num)();
+        });
     }));
 
     EXCEPTION_HANDLER.insert(Exception::UserEnvCall.try_into().unwrap(), Box::new(|_stval, context| {
@@ -0,0 +1,173 @@
+//! RISC-V AIA（Advanced Interrupt Architecture）驱动：APLIC + IMSIC
+//! APLIC的S态中断域工作在MSI模式，将中断源以MSI的形式发送到目标CPU的S态IMSIC中断文件；
+//! 中断号与中断标识（EIID）相同，CPU通过`stopei`取得中断。
+//! M态中断域的配置（将中断源委托给S态中断域、设置MSI地址）由M态软件（如OpenSBI）完成。
+//! 可用于QEMU `virt,aia=aplic-imsic`平台。
+
+use core::{arch::asm, ptr::write_volatile, sync::atomic::{AtomicU64, Ordering}};
+
+use lazy_init::LazyInit;
+
+use super::{InterruptController, IrqTrigger};
+
+const DOMAINCFG_OFFSET: usize = 0x0;
+const SOURCECFG_OFFSET: usize = 0x4;
+const SETIENUM_OFFSET: usize = 0x1edc;
+const CLRIENUM_OFFSET: usize = 0x1fdc;
+const SETIPNUM_LE_OFFSET: usize = 0x2000;
+const TARGET_OFFSET: usize = 0x3004;
+
+/// domaincfg：IE位使能中断域，DM位选择MSI模式
+const DOMAINCFG_IE: u32 = 1 << 8;
+const DOMAINCFG_DM_MSI: u32 = 1 << 2;
+/// sourcecfg的SM字段：各种触发方式
+const SOURCECFG_EDGE_RISING: u32 = 4;
+const SOURCECFG_EDGE_FALLING: u32 = 5;
+const SOURCECFG_LEVEL_HIGH: u32 = 6;
+const SOURCECFG_LEVEL_LOW: u32 = 7;
+const TARGET_HART_INDEX_SHIFT: u32 = 18;
+
+/// IMSIC的CSR
+const CSR_SISELECT: usize = 0x150;
+const CSR_SIREG: usize = 0x151;
+const CSR_STOPEI: usize = 0x15c;
+/// 通过siselect间接访问的IMSIC寄存器
+const IMSIC_EIDELIVERY: usize = 0x70;
+const IMSIC_EITHRESHOLD: usize = 0x72;
+const IMSIC_EIE0: usize = 0xc0;
+const STOPEI_ID_SHIFT: usize = 16;
+
+/// 支持的中断源数量（中断源0保留），需要IMSIC的中断文件支持至少255个中断标识
+pub const AIA_MAX_SOURCES: usize = 256;
+
+/// 由使用该模块的系统实现，提供APLIC的位置以及CPU与APLIC中hart索引的对应关系
+#[crate_interface::def_interface]
+pub trait AiaIf {
+    /// APLIC的S态中断域的MMIO基地址（需已映射，可直接访问）。QEMU virt平台上的物理地址为`0x0d00_0000`
+    fn aplic_base() -> usize;
+    /// id为`cpu_id`的CPU在APLIC中的hart索引。QEMU virt平台上为hart_id
+    fn aplic_hart_index(cpu_id: usize) -> usize;
+}
+
+pub(crate) struct Aia {
+    aplic_base: usize,
+    /// 边沿触发的中断源的位图，`complete`时不需要重新触发这些中断源
+    edge_sources: [AtomicU64; AIA_MAX_SOURCES / 64],
+}
+
+static AIA: LazyInit<Aia> = LazyInit::new();
+
+pub(crate) fn get() -> &'static dyn InterruptController {
+    AIA.init_by(Aia {
+        aplic_base: crate_interface::call_interface!(AiaIf::aplic_base()),
+        edge_sources: Default::default(),
+    });
+    &*AIA
+}
+
+macro_rules! csr_write {
+    ($csr:expr, $value:expr) => {
+        asm!("csrw {csr}, {value}", csr = const $csr, value = in(reg) $value)
+    };
+}
+
+/// 写入当前CPU的IMSIC中断文件中的寄存器
+unsafe fn imsic_write(select: usize, value: usize) {
+    csr_write!(CSR_SISELECT, select);
+    csr_write!(CSR_SIREG, value);
+}
+
+impl Aia {
+    fn aplic_write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.aplic_base + offset) as *mut u32, value) }
+    }
+
+    fn is_edge(&self, irq: usize) -> bool {
+        self.edge_sources[irq / 64].load(Ordering::Relaxed) & (1 << (irq % 64)) != 0
+    }
+
+    fn set_edge(&self, irq: usize, edge: bool) {
+        if edge {
+            self.edge_sources[irq / 64].fetch_or(1 << (irq % 64), Ordering::Relaxed);
+        }
+        else {
+            self.edge_sources[irq / 64].fetch_and(!(1 << (irq % 64)), Ordering::Relaxed);
+        }
+    }
+}
+
+impl InterruptController for Aia {
+    fn max_sources(&self) -> usize {
+        AIA_MAX_SOURCES
+    }
+
+    fn init(&self) {
+        self.aplic_write(DOMAINCFG_OFFSET, DOMAINCFG_IE | DOMAINCFG_DM_MSI);
+    }
+
+    /// 打开当前CPU的中断文件，并使能所有中断标识。中断源的使能与路由在APLIC中控制
+    fn init_current(&self, _cpu_id: usize) {
+        unsafe {
+            imsic_write(IMSIC_EIDELIVERY, 1);
+            imsic_write(IMSIC_EITHRESHOLD, 0);
+            // RV64上每个eie寄存器包含64个标识，且只使用偶数编号的寄存器
+            for i in 0 .. AIA_MAX_SOURCES / 64 {
+                imsic_write(IMSIC_EIE0 + i * 2, usize::MAX);
+            }
+        }
+    }
+
+    /// IMSIC中中断的优先级由中断标识决定（标识越小优先级越高），不支持由软件设置
+    fn set_priority(&self, _irq: usize, _priority: u32) -> bool {
+        false
+    }
+
+    fn supports_trigger(&self) -> bool {
+        true
+    }
+
+    /// MSI只能发送到一个CPU，因此发送到亲和性允许的、id最小的已初始化的CPU
+    fn configure(&self, irq: usize, enabled: bool, trigger: IrqTrigger, cpu_set: u64, online_cpus: u64) {
+        self.aplic_write(CLRIENUM_OFFSET, irq as u32);
+        let (sourcecfg, edge) = match trigger {
+            IrqTrigger::EdgeRising => (SOURCECFG_EDGE_RISING, true),
+            IrqTrigger::EdgeFalling => (SOURCECFG_EDGE_FALLING, true),
+            IrqTrigger::LevelHigh => (SOURCECFG_LEVEL_HIGH, false),
+            IrqTrigger::LevelLow => (SOURCECFG_LEVEL_LOW, false),
+        };
+        self.aplic_write(SOURCECFG_OFFSET + (irq - 1) * 4, sourcecfg);
+        self.set_edge(irq, edge);
+        let targets = cpu_set & online_cpus;
+        if !enabled || targets == 0 {
+            return;
+        }
+        let cpu_id = targets.trailing_zeros() as usize;
+        let hart_index: usize = crate_interface::call_interface!(AiaIf::aplic_hart_index(cpu_id));
+        self.aplic_write(TARGET_OFFSET + (irq - 1) * 4, ((hart_index as u32) << TARGET_HART_INDEX_SHIFT) | irq as u32);
+        self.aplic_write(SETIENUM_OFFSET, irq as u32);
+    }
+
+    /// 阈值不为0时，只有标识小于阈值的中断会被发送到当前CPU
+    fn set_current_threshold(&self, _cpu_id: usize, threshold: u32) {
+        unsafe { imsic_write(IMSIC_EITHRESHOLD, threshold as usize) }
+    }
+
+    /// 读写`stopei`以取得优先级最高的待处理中断，同时清除其pending位
+    fn claim(&self, _cpu_id: usize) -> Option<usize> {
+        let topei: usize;
+        unsafe { asm!("csrrw {topei}, {csr}, zero", topei = out(reg) topei, csr = const CSR_STOPEI) };
+        match topei >> STOPEI_ID_SHIFT {
+            0 => None,
+            irq => Some(irq),
+        }
+    }
+
+    /// MSI模式下，电平触发的中断源在发送MSI后不会因电平仍然有效而再次变为pending，
+    /// 因此按照AIA规范4.9.2节（与Linux的aplic-msi驱动相同），在处理完成后将中断号写入`setipnum_le`：若电平仍然有效，中断会再次变为pending
+    /// 边沿触发的中断源不需要该操作
+    fn complete(&self, _cpu_id: usize, irq: usize) {
+        if !self.is_edge(irq) {
+            self.aplic_write(SETIPNUM_LE_OFFSET, irq as u32);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! 外部中断控制器
+//! 不同的中断控制器（PLIC、AIA）通过`InterruptController`接口使用，外部中断的分发与配置接口对两者相同。
+//! 同时启用`plic`与`aia`时使用AIA，PLIC驱动不会被编译。
+
+#[cfg(all(feature = "plic", not(feature = "aia")))]
+pub(crate) mod plic;
+#[cfg(feature = "aia")]
+pub(crate) mod aia;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::collections::btree_map::BTreeMap;
+use lazy_init::LazyInit;
+use spinlock::SpinNoIrq;
+
+/// 中断控制器的硬件操作
+pub(crate) trait InterruptController: Sync {
+    /// 支持的中断源数量（中断源0保留）
+    fn max_sources(&self) -> usize;
+    /// 初始化中断控制器的全局部分，在主处理器上调用一次
+    fn init(&self);
+    /// 初始化当前CPU上与中断控制器相关的部分
+    fn init_current(&self, cpu_id: usize);
+    /// 返回值代表中断控制器是否支持设置中断源的优先级
+    fn set_priority(&self, irq: usize, priority: u32) -> bool;
+    /// 能否由软件设置中断源的触发方式
+    fn supports_trigger(&self) -> bool;
+    /// 写入中断源的使能位、触发方式与路由。`online_cpus`为已经初始化的CPU的位图
+    fn configure(&self, irq: usize, enabled: bool, trigger: IrqTrigger, cpu_set: u64, online_cpus: u64);
+    fn set_current_threshold(&self, cpu_id: usize, threshold: u32);
+    /// 取得一个待处理的中断，没有待处理的中断时返回None
+    fn claim(&self, cpu_id: usize) -> Option<usize>;
+    /// 中断处理完成
+    fn complete(&self, cpu_id: usize, irq: usize);
+}
+
+/// 中断源的触发方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IrqTrigger {
+    /// 上升沿触发
+    EdgeRising,
+    /// 下降沿触发
+    EdgeFalling,
+    /// 高电平触发，为默认的触发方式
+    LevelHigh,
+    /// 低电平触发
+    LevelLow,
+}
+
+/// 中断源的软件记录，用于在CPU上线时恢复配置
+#[derive(Clone, Copy)]
+struct IrqState {
+    enabled: bool,
+    /// 被线程化中断的上半部暂时屏蔽，与驱动设置的`enabled`相互独立
+    masked: bool,
+    trigger: IrqTrigger,
+    /// 允许处理该中断的CPU的位图
+    cpu_set: u64,
+}
+
+impl IrqState {
+    /// 写入硬件的使能位
+    fn hw_enabled(&self) -> bool {
+        self.enabled && !self.masked
+    }
+}
+
+impl Default for IrqState {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            masked: false,
+            trigger: IrqTrigger::LevelHigh,
+            cpu_set: u64::MAX,
+        }
+    }
+}
+
+static INTC: LazyInit<&'static dyn InterruptController> = LazyInit::new();
+
+/// 已经初始化了中断控制器的CPU的位图
+static ONLINE_CPUS: AtomicU64 = AtomicU64::new(0);
+
+/// 修改过配置的中断源，写入硬件时持有该锁，使软件记录与硬件一致
+static IRQ_STATES: SpinNoIrq<BTreeMap<usize, IrqState>> = SpinNoIrq::new(BTreeMap::new());
+
+fn update<F>(irq: usize, f: F)
+where F: FnOnce(&mut IrqState) {
+    assert!(irq != 0 && irq < INTC.max_sources(), "invalid irq number {}", irq);
+    let mut states = IRQ_STATES.lock();
+    let state = states.entry(irq).or_default();
+    f(state);
+    INTC.configure(irq, state.hw_enabled(), state.trigger, state.cpu_set, ONLINE_CPUS.load(Ordering::Acquire));
+}
+
+/// 在主处理器上选择并初始化中断控制器
+pub(crate) fn init_intc_on_main_processor(cpu_id: usize) {
+    #[cfg(feature = "aia")]
+    let intc: &'static dyn InterruptController = aia::get();
+    #[cfg(all(feature = "plic", not(feature = "aia")))]
+    let intc: &'static dyn InterruptController = plic::get();
+    INTC.init_by(intc);
+    INTC.init();
+    init_intc_on_current_processor(cpu_id);
+}
+
+/// 初始化当前CPU上与中断控制器相关的部分，并按软件记录恢复各个中断源的配置
+pub(crate) fn init_intc_on_current_processor(cpu_id: usize) {
+    assert!(cpu_id < u64::BITS as usize);
+    let states = IRQ_STATES.lock();
+    INTC.init_current(cpu_id);
+    let online_cpus = ONLINE_CPUS.fetch_or(1 << cpu_id, Ordering::AcqRel) | (1 << cpu_id);
+    for (&irq, state) in states.iter() {
+        INTC.configure(irq, state.hw_enabled(), state.trigger, state.cpu_set, online_cpus);
+    }
+}
+
+/// 返回值代表中断控制器是否支持设置优先级
+pub(crate) fn set_irq_priority(irq: usize, priority: u32) -> bool {
+    assert!(irq != 0 && irq < INTC.max_sources(), "invalid irq number {}", irq);
+    INTC.set_priority(irq, priority)
+}
+
+pub(crate) fn set_irq_enabled(irq: usize, enabled: bool) {
+    update(irq, |state| state.enabled = enabled);
+}
+
+/// 暂时屏蔽或解除屏蔽中断源，不改变驱动通过`set_irq_enabled`设置的使能状态
+/// 中断源只有在被使能且未被屏蔽时才会触发
+pub(crate) fn set_irq_masked(irq: usize, masked: bool) {
+    update(irq, |state| state.masked = masked);
+}
+
+/// 返回值代表中断控制器是否支持设置触发方式，不支持时不做修改
+pub(crate) fn set_irq_trigger(irq: usize, trigger: IrqTrigger) -> bool {
+    if !INTC.supports_trigger() {
+        return false;
+    }
+    update(irq, |state| state.trigger = trigger);
+    true
+}
+
+pub(crate) fn set_irq_affinity(irq: usize, cpu_set: u64) {
+    update(irq, |state| state.cpu_set = cpu_set);
+}
+
+pub(crate) fn set_current_threshold(cpu_id: usize, threshold: u32) {
+    INTC.set_current_threshold(cpu_id, threshold);
+}
+
+/// 处理外部中断：取得中断号，执行`handler`，再通知中断控制器处理完成，直到没有待处理的中断
+/// 多个CPU可能同时收到同一中断，只有一个CPU能取得中断号
+pub(crate) fn handle_irq<F>(cpu_id: usize, mut handler: F)
+where F: FnMut(usize) {
+    while let Some(irq) = INTC.claim(cpu_id) {
+        handler(irq);
+        INTC.complete(cpu_id, irq);
+    }
+}
@@ -0,0 +1,125 @@
+//! PLIC（Platform-Level Interrupt Controller）驱动
+//! 寄存器布局参照 RISC-V PLIC Specification，可用于QEMU virt平台。
+
+use core::ptr::{read_volatile, write_volatile};
+
+use lazy_init::LazyInit;
+
+use super::{InterruptController, IrqTrigger};
+
+const PRIORITY_OFFSET: usize = 0x0;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD_OFFSET: usize = 0x0;
+const CLAIM_OFFSET: usize = 0x4;
+
+/// PLIC规范允许的最大中断源数量（中断源0保留），平台上实际的数量由`PlicIf::plic_num_sources`提供
+pub const PLIC_MAX_SOURCES: usize = 1024;
+/// PLIC规范保证支持的最大优先级，0代表不会触发中断
+pub const PLIC_MAX_PRIORITY: u32 = 7;
+
+/// 由使用该模块的系统实现，提供PLIC的位置以及CPU与PLIC上下文的对应关系
+#[crate_interface::def_interface]
+pub trait PlicIf {
+    /// PLIC的MMIO基地址（需已映射，可直接访问）。QEMU virt平台上的物理地址为`0x0c00_0000`
+    fn plic_base() -> usize;
+    /// id为`cpu_id`的CPU在S态使用的PLIC上下文编号。QEMU virt平台上为`2 * hart_id + 1`
+    fn plic_context(cpu_id: usize) -> usize;
+    /// 平台上PLIC的中断源数量（包括保留的中断源0），不能超过`PLIC_MAX_SOURCES`。QEMU virt平台上为96
+    fn plic_num_sources() -> usize;
+}
+
+pub(crate) struct Plic {
+    base: usize,
+    /// 中断号需小于该值
+    num_sources: usize,
+}
+
+static PLIC: LazyInit<Plic> = LazyInit::new();
+
+pub(crate) fn get() -> &'static dyn InterruptController {
+    let num_sources: usize = crate_interface::call_interface!(PlicIf::plic_num_sources());
+    assert!(num_sources <= PLIC_MAX_SOURCES, "PLIC cannot have more than {} sources", PLIC_MAX_SOURCES);
+    PLIC.init_by(Plic {
+        base: crate_interface::call_interface!(PlicIf::plic_base()),
+        num_sources,
+    });
+    &*PLIC
+}
+
+fn context_of(cpu_id: usize) -> usize {
+    crate_interface::call_interface!(PlicIf::plic_context(cpu_id))
+}
+
+impl Plic {
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn context_reg(&self, context: usize, offset: usize) -> *mut u32 {
+        self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + offset)
+    }
+
+    fn set_enable(&self, context: usize, irq: usize, enable: bool) {
+        let reg = self.reg(ENABLE_OFFSET + context * ENABLE_STRIDE + irq / 32 * 4);
+        let mask = 1 << (irq % 32);
+        unsafe {
+            let value = read_volatile(reg);
+            write_volatile(reg, if enable { value | mask } else { value & !mask });
+        }
+    }
+
+    fn set_threshold(&self, context: usize, threshold: u32) {
+        unsafe { write_volatile(self.context_reg(context, THRESHOLD_OFFSET), threshold) }
+    }
+}
+
+impl InterruptController for Plic {
+    fn max_sources(&self) -> usize {
+        self.num_sources
+    }
+
+    fn init(&self) { }
+
+    /// 当前CPU接受所有优先级的中断
+    fn init_current(&self, cpu_id: usize) {
+        self.set_threshold(context_of(cpu_id), 0);
+    }
+
+    fn set_priority(&self, irq: usize, priority: u32) -> bool {
+        unsafe { write_volatile(self.reg(PRIORITY_OFFSET + irq * 4), priority) }
+        true
+    }
+
+    /// 中断源的触发方式由平台的中断网关决定
+    fn supports_trigger(&self) -> bool {
+        false
+    }
+
+    /// 在亲和性允许的每个CPU的上下文中使能该中断源
+    fn configure(&self, irq: usize, enabled: bool, _trigger: IrqTrigger, cpu_set: u64, online_cpus: u64) {
+        for cpu_id in 0 .. u64::BITS as usize {
+            if online_cpus & (1 << cpu_id) != 0 {
+                self.set_enable(context_of(cpu_id), irq, enabled && cpu_set & (1 << cpu_id) != 0);
+            }
+        }
+    }
+
+    fn set_current_threshold(&self, cpu_id: usize, threshold: u32) {
+        self.set_threshold(context_of(cpu_id), threshold);
+    }
+
+    fn claim(&self, cpu_id: usize) -> Option<usize> {
+        match unsafe { read_volatile(self.context_reg(context_of(cpu_id), CLAIM_OFFSET)) } {
+            0 => None,
+            irq => Some(irq as usize),
+        }
+    }
+
+    /// 通知PLIC中断处理完成，之后该中断源才能再次触发
+    fn complete(&self, cpu_id: usize, irq: usize) {
+        unsafe { write_volatile(self.context_reg(context_of(cpu_id), CLAIM_OFFSET), irq as u32) }
+    }
+}
@@ -8,5 +8,9 @@ mod entry;
 mod handler;
 #[cfg(feature = "timer")]
 mod timer;
+#[cfg(any(feature = "plic", feature = "aia"))]
+mod intc;
+#[cfg(any(feature = "plic", feature = "aia"))]
+mod threaded;
 
 pub use api::*;
\ No newline at end of file
@@ -0,0 +1,78 @@
+//! 线程化的中断处理：将任务绑定到中断号
+//! 中断到来时，上半部只屏蔽该中断源并唤醒绑定的任务；任务以普通的调度优先级运行处理函数，其中可以阻塞。
+//! 处理函数返回后，任务解除屏蔽，并等待下一次中断。
+//! 屏蔽与驱动的`disable_irq`/`enable_irq`相互独立，因此处理函数中调用的`disable_irq`不会被解除屏蔽撤销。
+//! 任务被终止后，中断源被禁用，上半部被注销。
+
+use core::{cell::Cell, future::Future};
+
+use alloc::{boxed::Box, sync::Arc};
+use task_management::{sync::Semaphore, task_local, SpawnError, Task, TaskBuilder};
+
+use crate::{api::enable_irq, handler::EXTINTR_HANDLER, intc};
+
+/// 绑定了中断号的任务退出（包括被`kill`终止）时，禁用中断源、解除屏蔽并注销上半部
+/// 被终止的线程栈上的对象不会被析构，因此保存在任务局部变量中，其总会在任务退出、资源被回收时析构
+struct IrqThreadGuard {
+    /// 0代表还未绑定
+    irq_num: Cell<usize>,
+}
+
+impl Drop for IrqThreadGuard {
+    fn drop(&mut self) {
+        let irq_num = self.irq_num.get();
+        if irq_num != 0 {
+            intc::set_irq_enabled(irq_num, false);
+            intc::set_irq_masked(irq_num, false);
+            EXTINTR_HANDLER.remove(irq_num);
+        }
+    }
+}
+
+task_local! {
+    static IRQ_THREAD: IrqThreadGuard = IrqThreadGuard { irq_num: Cell::new(0) };
+}
+
+/// 在绑定的任务中调用：注册上半部并使能中断源，返回任务等待中断时使用的信号量
+/// 在任务开始运行后才注册，使任务在第一次运行前被终止时也不会留下上半部
+/// `to_global`代表中断到来时，将任务唤醒到全局调度器还是当前CPU的调度器
+fn bind_current(irq_num: usize, to_global: bool) -> Arc<Semaphore> {
+    let pending = Arc::new(Semaphore::new(0));
+    let top_half_pending = pending.clone();
+    EXTINTR_HANDLER.insert(irq_num, Box::new(move || {
+        intc::set_irq_masked(irq_num, true);
+        if to_global {
+            top_half_pending.release_to_global();
+        }
+        else {
+            top_half_pending.release();
+        }
+    }));
+    IRQ_THREAD.with(|guard| guard.irq_num.set(irq_num));
+    enable_irq(irq_num);
+    pending
+}
+
+pub(crate) fn register_threaded_irq_handler<F>(irq_num: usize, to_global: bool, builder: TaskBuilder, mut handler: F) -> Result<Arc<Task>, SpawnError>
+where F: FnMut() + Send + 'static {
+    builder.spawn(move || {
+        let pending = bind_current(irq_num, to_global);
+        loop {
+            pending.acquire();
+            handler();
+            intc::set_irq_masked(irq_num, false);
+        }
+    })
+}
+
+pub(crate) fn register_threaded_irq_handler_async<F, Fut>(irq_num: usize, to_global: bool, builder: TaskBuilder, mut handler: F) -> Result<Arc<Task>, SpawnError>
+where F: FnMut() -> Fut + Send + 'static, Fut: Future<Output = ()> + Send + 'static {
+    builder.spawn_async(async move {
+        let pending = bind_current(irq_num, to_global);
+        loop {
+            pending.acquire_async().await;
+            handler().await;
+            intc::set_irq_masked(irq_num, false);
+        }
+    })
+}
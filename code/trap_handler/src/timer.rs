@@ -4,7 +4,7 @@ use alloc::boxed::Box;
 use axlog::debug;
 use lazy_init::LazyInit;
 use riscv::register::{scause::{Interrupt, Trap}, time};
-use task_management::{scheduler_tick_current, TaskContext};
+use task_management::{check_timers_current, init_timebase_frequency, scheduler_tick_current, TaskContext};
 
 #[cfg(feature = "preempt")]
 use task_management::current_can_preempt;
@@ -40,6 +40,9 @@ pub(crate) fn init_timer_on_main_processor() {
     #[cfg(not(feature = "smp"))]
     TIMEBASE_FREQUENCY.init_by(crate_interface::call_interface!(CurrentTimebaseFrequency::current_timebase_frequency()));
 
+    // 供task_management模块中的睡眠等接口使用
+    init_timebase_frequency(current_timebase_frequency());
+
     // register_trap_handler(Trap::Interrupt(Interrupt::SupervisorTimer), timer_interrupt_handler);
     INTERRUPT_HANDLER.insert(Interrupt::SupervisorTimer.try_into().unwrap(), Box::new(timer_interrupt_handler));
     sbi_rt::set_timer(0);
@@ -51,12 +54,18 @@ pub(crate) fn init_timer_on_secondary_processor() {
     sbi_rt::set_timer(0);
 }
 
-fn timer_interrupt_handler(_stval: usize, context: &mut TaskContext) {
+fn current_timebase_frequency() -> usize {
     #[cfg(feature = "smp")]
     let timebase_frequency: usize = TIMEBASE_FREQUENCY.with_current(|tf| **tf);
     #[cfg(not(feature = "smp"))]
     let timebase_frequency: usize = *TIMEBASE_FREQUENCY;
 
+    timebase_frequency
+}
+
+fn timer_interrupt_handler(_stval: usize, context: &mut TaskContext) {
+    let timebase_frequency = current_timebase_frequency();
+
     let now = time::read();
     let next_deadline = now + timebase_frequency / TIMER_FREQUENCY;
     sbi_rt::set_timer(next_deadline as u64);
@@ -64,6 +73,8 @@ fn timer_interrupt_handler(_stval: usize, context: &mut TaskContext) {
     // 时钟中断处理函数的实际功能
     // #[cfg(feature = "log")]
     // debug!("Receive timer interrupt!");
+    // 唤醒到期的睡眠任务
+    check_timers_current();
     let need_resched = scheduler_tick_current();
     #[cfg(feature = "preempt")]
     if need_resched && current_can_preempt() {